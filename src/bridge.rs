@@ -0,0 +1,704 @@
+use crate::config::{Config, ConfigError, ConflictStrategy, Endpoint, Mapping};
+use crate::osc_device::{Event, OscDevice, Stats};
+use crate::poller::Poller;
+use crate::profile::{DeviceProfile, ParamId};
+use crate::sync::{Settings, Strategy, Sync};
+use crate::value::Value;
+
+use rosc::OscMessage;
+use std::{collections::HashMap, future::poll_fn, task::Poll, time::Duration};
+use tokio::time::{Instant, MissedTickBehavior, interval, interval_at};
+
+// Interval for polling the devices and checking for expired masters
+const MAIN_DELTA: Duration = Duration::from_millis(100);
+
+// Interval for logging the connection health of all devices
+const STATS_INTERVAL: Duration = Duration::from_secs(60);
+
+// Placeholder period of the resync timer if the resync is disabled
+const NO_RESYNC: Duration = Duration::from_secs(3600);
+
+// Interval for logging the progress while waiting for the initial values
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct Bridge {
+    devices: Vec<Device>,
+    params: Vec<Param>,
+    // Whether all initial values have been received and reconciled, nothing is forwarded before
+    reconciled: bool,
+    started: Instant,
+    // Time after which the synchronisation starts even if values are missing
+    startup_timeout: Option<Duration>,
+    last_progress: Instant,
+    resync_interval: Option<Duration>,
+    // Only log the drift found by the resync
+    dry_run: bool,
+}
+
+struct Device {
+    name: String,
+    osc: OscDevice,
+    profile: Box<dyn DeviceProfile>,
+    // Schedules the queries of all mapped parameters
+    poller: Poller,
+    // Registration for change notifications and the interval it has to be renewed in
+    keep_alive: Option<(OscMessage, Duration)>,
+    last_keep_alive: Option<Instant>,
+    // Maps device parameters to the bridge parameters they update
+    routes: HashMap<ParamId, Vec<Route>>,
+    // Set once the device task has stopped
+    closed: bool,
+    // Statistics at the time of the last report
+    last_stats: Stats,
+}
+
+struct Param {
+    channel: usize,
+    sync: Sync<Value>,
+    // One target per member of the sync
+    targets: Vec<Target>,
+    // Member whose value wins in the periodic resync
+    resync_master: Option<usize>,
+}
+
+struct Target {
+    device: usize,
+    params: Vec<ParamId>,
+    // Last received or sent value of every parameter
+    parts: Vec<Option<Value>>,
+}
+
+struct Route {
+    param: usize,
+    member: usize,
+    // Position of the device parameter in the target
+    component: usize,
+}
+
+impl Bridge {
+    /// Build all sync instances, routes and subscriptions for the given mapping, `devices` has to
+    /// contain an `OscDevice` and its profile for every device in the configuration
+    pub fn new(
+        config: &Config,
+        devices: Vec<(String, OscDevice, Box<dyn DeviceProfile>)>,
+    ) -> Result<Self, ConfigError> {
+        let mut devices: Vec<Device> = devices
+            .into_iter()
+            .map(|(name, osc, profile)| Device {
+                name,
+                osc,
+                profile,
+                poller: Poller::default(),
+                keep_alive: None,
+                last_keep_alive: None,
+                routes: HashMap::new(),
+                closed: false,
+                last_stats: Stats::default(),
+            })
+            .collect();
+
+        let mut params = Vec::new();
+
+        for mapping in &config.mappings {
+            let default_master = mapping
+                .members
+                .iter()
+                .position(|e| Some(&e.device) == config.startup.master.as_ref());
+
+            let resync_master = mapping.members.iter().position(|e| {
+                Some(&e.device)
+                    == config
+                        .resync
+                        .master
+                        .as_ref()
+                        .or(config.startup.master.as_ref())
+            });
+            let settings = sync_settings(mapping);
+
+            // Values that don't survive the way to the canonical value and back make the
+            // members fight each other
+            for endpoint in &mapping.members {
+                let error = endpoint.transform.round_trip_error();
+                if error > endpoint.transform.round_trip_tolerance() {
+                    log::warn!(
+                        "Transform of {} in mapping '{}' does not round-trip, values change by up to {}",
+                        endpoint.device,
+                        mapping.name,
+                        error
+                    );
+                }
+            }
+
+            for n in mapping.channels.iter() {
+                let index = params.len();
+                let mut sync = Sync::new(format!("{}{:02}", mapping.name, n), settings.clone());
+                let mut targets = Vec::new();
+
+                for endpoint in &mapping.members {
+                    let (to, from) = (endpoint.transform.clone(), endpoint.transform.clone());
+                    let (to_xy, from_xy) =
+                        (endpoint.coordinates.clone(), endpoint.coordinates.clone());
+                    let member = sync.add_member(
+                        move |v: Value| {
+                            let v = v.map_number(|x| to.to_canonical(x));
+                            match &to_xy {
+                                Some(coordinates) => coordinates.to_canonical(v),
+                                None => v,
+                            }
+                        },
+                        move |v: Value| {
+                            let v = match &from_xy {
+                                Some(coordinates) => coordinates.to_member(v),
+                                None => v,
+                            };
+                            v.map_number(|x| from.to_member(x))
+                        },
+                    );
+                    targets.push(add_endpoint(
+                        &mut devices,
+                        mapping,
+                        endpoint,
+                        n,
+                        index,
+                        member,
+                    )?);
+                }
+
+                sync.set_default_master(default_master);
+
+                params.push(Param {
+                    channel: n,
+                    sync,
+                    targets,
+                    resync_master,
+                });
+            }
+        }
+
+        for device in &mut devices {
+            let mut params: Vec<ParamId> = device.routes.keys().cloned().collect();
+            params.sort();
+            device.keep_alive = device.profile.keep_alive();
+
+            let poll_rate = config.devices[&device.name].poll_rate(&*device.profile);
+            device.poller = Poller::new(&*device.profile, &params, poll_rate);
+        }
+
+        Ok(Bridge {
+            devices,
+            params,
+            reconciled: false,
+            started: Instant::now(),
+            startup_timeout: config.startup.timeout(),
+            last_progress: Instant::now(),
+            resync_interval: config.resync.interval(),
+            dry_run: config.resync.dry_run,
+        })
+    }
+
+    /// Forward updates as they arrive, `monitor` is a list of channels whose state is logged
+    /// periodically
+    pub async fn run(&mut self, monitor: &[usize]) {
+        let mut ticks = interval(MAIN_DELTA);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        let mut stats_ticks = interval_at(Instant::now() + STATS_INTERVAL, STATS_INTERVAL);
+
+        let resync_period = self.resync_interval.unwrap_or(NO_RESYNC);
+        let mut resync_ticks = interval_at(Instant::now() + resync_period, resync_period);
+
+        loop {
+            tokio::select! {
+                (device, event) = next_event(&mut self.devices) => {
+                    match event {
+                        Event::Message(msg) => self.receive(device, &msg),
+                        Event::Online => {
+                            self.set_online(device, true);
+                            // Query everything again, the device might have restarted
+                            self.devices[device].query_all();
+                        }
+                        Event::Offline => self.set_online(device, false),
+                    }
+                }
+                _ = stats_ticks.tick() => {
+                    self.log_stats();
+                }
+                _ = resync_ticks.tick(), if self.resync_interval.is_some() => {
+                    self.resync();
+                }
+                _ = ticks.tick() => {
+                    self.check_devices();
+
+                    for &channel in monitor {
+                        self.monitor(channel);
+                    }
+
+                    if self.reconciled {
+                        self.flush();
+                    } else {
+                        self.try_reconcile();
+                    }
+
+                    // Ask for new values
+                    self.poll();
+                }
+            }
+        }
+    }
+
+    /// Stop all device tasks after sending the queued messages
+    pub async fn close(self) {
+        for device in self.devices {
+            device.osc.close().await;
+        }
+    }
+
+    fn set_online(&mut self, device: usize, online: bool) {
+        for param in &mut self.params {
+            for (member, target) in param.targets.iter().enumerate() {
+                if target.device == device {
+                    param.sync.set_online(member, online);
+                }
+            }
+        }
+    }
+
+    fn log_stats(&mut self) {
+        let secs = STATS_INTERVAL.as_secs_f32();
+
+        for device in &mut self.devices {
+            let stats = device.osc.stats();
+            let last = std::mem::replace(&mut device.last_stats, stats.clone());
+
+            let last_received = match stats.last_received {
+                Some(t) => format!("{:.1}s ago", t.elapsed().as_secs_f32()),
+                None => "never".to_owned(),
+            };
+
+            log::info!(
+                "{}: {}, last message {}, {:.1} msg/s in, {:.1} msg/s out, {} send errors, {} coalesced, {} dropped",
+                device.name,
+                if stats.online { "online" } else { "offline" },
+                last_received,
+                (stats.received - last.received) as f32 / secs,
+                (stats.sent - last.sent) as f32 / secs,
+                stats.send_errors,
+                stats.coalesced,
+                stats.dropped,
+            );
+        }
+    }
+
+    fn check_devices(&mut self) {
+        for device in &mut self.devices {
+            if !device.closed && device.osc.is_closed() {
+                log::error!("Connection to {} has stopped, ignoring it", device.name);
+                device.closed = true;
+            }
+        }
+    }
+
+    fn poll(&mut self) {
+        for device in &mut self.devices {
+            device.poll();
+        }
+    }
+
+    /// Update all parameters contained in `msg` and forward them immediately
+    fn receive(&mut self, device: usize, msg: &OscMessage) {
+        let dev = &self.devices[device];
+        log::debug!("Got {} message {:?}", dev.name, msg);
+
+        // Collect all parameters of the message first, so coupled values are updated as a whole
+        let mut touched: Vec<(usize, usize)> = Vec::new();
+
+        for (id, value) in dev.profile.parse(msg) {
+            for route in dev.routes.get(&id).into_iter().flatten() {
+                self.params[route.param].targets[route.member].parts[route.component] =
+                    Some(value.clone());
+
+                if !touched.contains(&(route.param, route.member)) {
+                    touched.push((route.param, route.member));
+                }
+            }
+        }
+
+        let mut changed = Vec::new();
+
+        // Device parameters whose values have changed, they are polled more often for a while
+        let mut moved = Vec::new();
+
+        for (index, member) in touched {
+            let param = &mut self.params[index];
+            let target = &param.targets[member];
+
+            let Some(value) = target.value() else {
+                continue;
+            };
+
+            if param.sync.update(member, value) {
+                moved.extend(target.params.iter().cloned());
+                if !changed.contains(&index) {
+                    changed.push(index);
+                }
+            }
+        }
+
+        for id in &moved {
+            self.devices[device].poller.changed(id);
+        }
+
+        if self.reconciled {
+            for param in changed {
+                flush_param(&self.devices, &mut self.params[param]);
+            }
+        }
+    }
+
+    /// Apply the startup policy once all values have been received from the devices, or with the
+    /// values that are there once the startup timeout has expired
+    fn try_reconcile(&mut self) {
+        let missing = self.params.iter().filter(|p| !p.sync.is_complete()).count();
+        let timed_out = self
+            .startup_timeout
+            .is_some_and(|t| self.started.elapsed() >= t);
+
+        if missing == 0 {
+            log::info!("Received all initial values, reconciling");
+        } else if timed_out {
+            // Number of missing values per device
+            let mut counts = vec![0; self.devices.len()];
+            for param in &self.params {
+                for (member, target) in param.targets.iter().enumerate() {
+                    if !param.sync.is_known(member) {
+                        counts[target.device] += 1;
+                    }
+                }
+            }
+            let counts: Vec<String> = counts
+                .iter()
+                .zip(&self.devices)
+                .filter(|&(&count, _)| count > 0)
+                .map(|(count, device)| format!("{} from {}", count, device.name))
+                .collect();
+
+            log::warn!(
+                "Initial values still missing after {:.1}s ({}), reconciling without them",
+                self.started.elapsed().as_secs_f32(),
+                counts.join(", ")
+            );
+        } else {
+            if self.last_progress.elapsed() >= PROGRESS_INTERVAL {
+                log::info!(
+                    "Waiting for initial values, {} of {} parameters are incomplete",
+                    missing,
+                    self.params.len()
+                );
+                self.last_progress = Instant::now();
+            }
+            return;
+        }
+
+        for param in &mut self.params {
+            let old: Vec<Value> = (0..param.targets.len())
+                .map(|member| param.sync.value(member).clone())
+                .collect();
+
+            for (value, member) in param.sync.reconcile() {
+                log::info!(
+                    "{}: changing {} from {} to {}",
+                    param.sync.name(),
+                    self.devices[param.targets[member].device].name,
+                    old[member],
+                    value
+                );
+                send_value(&self.devices, &mut param.targets[member], value);
+            }
+        }
+
+        self.reconciled = true;
+    }
+
+    /// Make all parameters that have drifted apart match the resync master again, or only report
+    /// them in a dry run
+    fn resync(&mut self) {
+        if !self.reconciled {
+            return;
+        }
+
+        let total = self.params.len();
+        let mut drifted = 0;
+        let mut largest: Option<(f64, &str)> = None;
+
+        for param in &mut self.params {
+            let old: Vec<Value> = (0..param.targets.len())
+                .map(|member| param.sync.value(member).clone())
+                .collect();
+
+            let updates = param.sync.resync(param.resync_master, self.dry_run);
+            if updates.is_empty() {
+                continue;
+            }
+
+            drifted += 1;
+
+            for (value, member) in updates {
+                if let Some(distance) = value.distance(&old[member])
+                    && largest.is_none_or(|(d, _)| distance > d)
+                {
+                    largest = Some((distance, param.sync.name()));
+                }
+
+                log::info!(
+                    "{}: {} {} from {} to {}",
+                    param.sync.name(),
+                    if self.dry_run {
+                        "would change"
+                    } else {
+                        "changing"
+                    },
+                    self.devices[param.targets[member].device].name,
+                    old[member],
+                    value
+                );
+
+                if !self.dry_run {
+                    send_value(&self.devices, &mut param.targets[member], value);
+                }
+            }
+        }
+
+        match largest {
+            Some((distance, name)) => log::info!(
+                "Resync: {} of {} parameters out of sync, largest difference {} ({})",
+                drifted,
+                total,
+                distance,
+                name
+            ),
+            None => log::info!("Resync: {} of {} parameters out of sync", drifted, total),
+        }
+    }
+
+    /// Send all values that have to be synchronised
+    fn flush(&mut self) {
+        for param in &mut self.params {
+            flush_param(&self.devices, param);
+        }
+    }
+
+    /// Log the state of all parameters of the given channel
+    fn monitor(&self, channel: usize) {
+        for param in self.params.iter().filter(|p| p.channel == channel) {
+            let values: Vec<String> = param
+                .targets
+                .iter()
+                .enumerate()
+                .map(|(member, target)| {
+                    let device = &self.devices[target.device].name;
+                    format!("{} {}", device, param.sync.value(member))
+                })
+                .collect();
+
+            let master = param
+                .sync
+                .current_master()
+                .map(|member| self.devices[param.targets[member].device].name.as_str());
+
+            log::info!(
+                "{}:\t{}\tMaster: {:?}",
+                param.sync.name(),
+                values.join("\t"),
+                master,
+            );
+        }
+    }
+}
+
+impl Device {
+    fn send(&self, msg: OscMessage) {
+        if let Err(err) = self.osc.send(msg) {
+            log::debug!("Failed to send to {}: {}", self.name, err);
+        }
+    }
+
+    /// Register for change notifications and query all mapped values again, within the poll
+    /// rate
+    fn query_all(&mut self) {
+        self.last_keep_alive = None;
+        self.renew();
+        self.poller.reset();
+    }
+
+    /// Query the values that are due and renew the registration for notifications
+    fn poll(&mut self) {
+        for msg in self.poller.poll() {
+            self.send(msg);
+        }
+        self.renew();
+    }
+
+    fn renew(&mut self) {
+        let Some((msg, interval)) = &self.keep_alive else {
+            return;
+        };
+
+        if self
+            .last_keep_alive
+            .is_none_or(|t| t.elapsed() >= *interval)
+        {
+            self.send(msg.clone());
+            self.last_keep_alive = Some(Instant::now());
+        }
+    }
+}
+
+impl Target {
+    /// Value of the whole target, `None` until all parameters have been received or sent
+    fn value(&self) -> Option<Value> {
+        if let [part] = self.parts.as_slice() {
+            return part.clone();
+        }
+
+        let parts: Option<Vec<Value>> = self.parts.iter().cloned().collect();
+        parts.map(Value::Array)
+    }
+}
+
+fn flush_param(devices: &[Device], param: &mut Param) {
+    for (value, member) in param.sync.flush() {
+        send_value(devices, &mut param.targets[member], value);
+    }
+}
+
+/// Send `value` to the device of `target` and remember its parts, so that the echo of one of
+/// them is combined with the others that have been sent
+fn send_value(devices: &[Device], target: &mut Target, value: Value) {
+    let device = &devices[target.device];
+
+    let msgs = match (target.params.as_slice(), &value) {
+        ([param], _) => device.profile.render(param, &value).map(|msg| vec![msg]),
+        (params, Value::Array(values)) if params.len() == values.len() => {
+            let values: Vec<_> = params.iter().cloned().zip(values.iter().cloned()).collect();
+            device.profile.render_group(&values)
+        }
+        _ => None,
+    };
+
+    let Some(msgs) = msgs else {
+        let names: Vec<&str> = target.params.iter().map(|p| p.name.as_str()).collect();
+        log::warn!(
+            "Can't send {} as {} {} to {}",
+            value,
+            names.join("/"),
+            target.params[0].index,
+            device.name
+        );
+        return;
+    };
+
+    match (target.parts.as_mut_slice(), value) {
+        ([part], value) => *part = Some(value),
+        (parts, Value::Array(values)) => {
+            for (part, value) in parts.iter_mut().zip(values) {
+                *part = Some(value);
+            }
+        }
+        _ => {}
+    }
+
+    for msg in msgs {
+        log::debug!("Sending {:?} to {}", msg, device.name);
+        device.send(msg);
+    }
+}
+
+async fn next_event(devices: &mut [Device]) -> (usize, Event) {
+    poll_fn(|cx| {
+        for (i, device) in devices.iter_mut().enumerate() {
+            if let Poll::Ready(Some(event)) = device.osc.poll_recv(cx) {
+                return Poll::Ready((i, event));
+            }
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+fn sync_settings(mapping: &Mapping) -> Settings {
+    let strategy = match mapping.strategy {
+        ConflictStrategy::LastWriter => Strategy::LastWriter,
+        ConflictStrategy::NeverForce => Strategy::NeverForce,
+        ConflictStrategy::Priority => Strategy::Priority,
+        ConflictStrategy::Authoritative => {
+            let authority = mapping
+                .members
+                .iter()
+                .position(|e| Some(&e.device) == mapping.authority.as_ref())
+                .expect("Authority has not been validated");
+            Strategy::Authoritative(authority)
+        }
+    };
+
+    let mut settings = Settings {
+        strategy,
+        ..Settings::default()
+    };
+
+    if let Some(lockout) = mapping.lockout {
+        settings.lockout = Duration::from_secs_f32(lockout);
+    }
+    if let Some(eps) = mapping.eps {
+        settings.eps = eps;
+    }
+    if let Some(force_eps) = mapping.force_eps {
+        settings.force_eps = force_eps;
+    }
+    settings.echo_eps = mapping.echo_eps.unwrap_or(settings.eps);
+
+    settings
+}
+
+fn add_endpoint(
+    devices: &mut [Device],
+    mapping: &Mapping,
+    endpoint: &Endpoint,
+    n: usize,
+    param: usize,
+    member: usize,
+) -> Result<Target, ConfigError> {
+    let device = devices
+        .iter()
+        .position(|d| d.name == endpoint.device)
+        .expect("Device has not been created");
+
+    let dev = &mut devices[device];
+    let mut params = Vec::new();
+
+    for (component, name) in endpoint.param.iter().enumerate() {
+        if !dev.profile.supports(name) {
+            return Err(ConfigError::UnknownParam(
+                mapping.name.clone(),
+                endpoint.device.clone(),
+                name.clone(),
+            ));
+        }
+
+        let id = match dev.profile.bank(name, endpoint.bank()) {
+            Some(bank) => ParamId::banked(name, bank, n),
+            None => ParamId::new(name, n),
+        };
+        dev.routes.entry(id.clone()).or_default().push(Route {
+            param,
+            member,
+            component,
+        });
+        params.push(id);
+    }
+
+    Ok(Target {
+        device,
+        parts: vec![None; params.len()],
+        params,
+    })
+}
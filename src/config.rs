@@ -0,0 +1,453 @@
+use crate::profile::DeviceProfile;
+use crate::transform::{Coordinates, Transform};
+use crate::value::ValueKind;
+use serde::{Deserialize, Deserializer};
+use std::{collections::BTreeMap, fs, io, net::Ipv4Addr, path::Path, time::Duration};
+use thiserror::Error;
+
+// Mapping used if no configuration file is given, equivalent to a WING <-> DS100 bridge for 40
+// inputs and 4 reverb buses
+const DEFAULT_CONFIG: &str = include_str!("../config/default.toml");
+
+// Devices that have not answered for this long count as offline
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Shortest timeout of a device, the connection is checked twice per timeout
+const MIN_TIMEOUT: Duration = Duration::from_millis(10);
+
+// Lowest poll or send rate in messages per second, the time until the next message has to fit
+// into a `Duration`
+const MIN_RATE: f32 = 0.1;
+
+// Shortest time between two resync passes
+const MIN_RESYNC_INTERVAL: Duration = Duration::from_millis(100);
+
+// Time to wait for the initial values before the synchronisation starts without the missing ones
+const DEFAULT_STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub devices: BTreeMap<String, DeviceConfig>,
+    #[serde(rename = "mapping")]
+    pub mappings: Vec<Mapping>,
+    #[serde(default)]
+    pub startup: Startup,
+    #[serde(default)]
+    pub resync: Resync,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Startup {
+    /// Device whose values win if the devices disagree once all values have been received, and
+    /// whenever they drift apart later on. Without it, nothing is changed until a value is moved.
+    pub master: Option<String>,
+    /// Seconds to wait for all initial values, afterwards the members that haven't reported
+    /// their values get those of the master. 0 waits forever.
+    pub timeout: Option<f32>,
+}
+
+/// Periodic pass that makes parameters match again which have drifted apart by less than the
+/// force threshold of their mapping
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Resync {
+    /// Seconds between two passes, disabled if not given or 0
+    pub interval: Option<f32>,
+    /// Device whose values win, defaults to the startup master
+    pub master: Option<String>,
+    /// Only log the differences instead of changing anything
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+    /// Built-in profile of the device, if not given the parameters have to be declared in `params`
+    pub profile: Option<ProfileKind>,
+    pub ip: Option<Ipv4Addr>,
+    /// Defaults to the port of the profile
+    pub port: Option<u16>,
+    /// Defaults to the local port of the profile
+    pub local_port: Option<u16>,
+    /// Send the messages queued at the same time as OSC bundles
+    #[serde(default)]
+    pub bundle: bool,
+    /// WING, X32 or X-Air send that channel send parameters refer to, defaults to 1
+    pub send: Option<u8>,
+    /// DS100 coordinate mapping (1 to 4) that positions refer to, defaults to 1
+    pub mapping_area: Option<u8>,
+    /// Seconds without any message after which the device counts as offline, 0 disables the
+    /// check
+    pub timeout: Option<f32>,
+    /// Queries sent per second at most, defaults to the rate of the profile, 0 removes the
+    /// limit
+    pub poll_rate: Option<f32>,
+    /// Messages sent per second at most, including queries, unlimited if not given or 0
+    pub send_rate: Option<f32>,
+    #[serde(default)]
+    pub params: BTreeMap<String, ParamConfig>,
+}
+
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfileKind {
+    Wing,
+    Ds100,
+    #[serde(alias = "m32")]
+    X32,
+    #[serde(rename = "xair", alias = "mr")]
+    XAir,
+}
+
+/// Parameter of a device without a built-in profile, the address templates may contain `{n}` as
+/// a placeholder for the channel number
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ParamConfig {
+    /// Address that updates are sent to
+    pub address: String,
+    /// Address that is polled and on which updates are received, defaults to `address`
+    pub listen: Option<String>,
+    /// Position of the value in the argument list of received messages
+    #[serde(default)]
+    pub arg: usize,
+    /// Type the values are sent as, defaults to the type of the other side
+    #[serde(rename = "type")]
+    pub kind: Option<ValueKind>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Mapping {
+    pub name: String,
+    pub channels: ChannelRange,
+    /// Devices that control the same values, at least two
+    pub members: Vec<Endpoint>,
+    /// How conflicting changes are resolved
+    #[serde(default)]
+    pub strategy: ConflictStrategy,
+    /// Device whose values always win with the `authoritative` strategy
+    pub authority: Option<String>,
+    /// Seconds the last changed member keeps control
+    pub lockout: Option<f32>,
+    /// Differences up to this value count as equal
+    pub eps: Option<f32>,
+    /// Differences larger than this are forced to the startup master
+    pub force_eps: Option<f32>,
+    /// Received values this close to a value the bridge has just sent count as its echo,
+    /// defaults to `eps`
+    pub echo_eps: Option<f32>,
+}
+
+#[derive(Deserialize, Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictStrategy {
+    /// The most recent change wins, members that drift apart are forced to the startup master
+    #[default]
+    LastWriter,
+    /// Like `last_writer`, but members that drift apart are left alone
+    NeverForce,
+    /// Only changes of the `authority` are forwarded, all other members are reset to its value
+    Authoritative,
+    /// Members listed first take over at any time, changes of the others are reverted until the
+    /// lockout of the current master has expired
+    Priority,
+}
+
+#[derive(Deserialize, Debug, Copy, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelRange {
+    pub from: usize,
+    pub to: usize,
+}
+
+/// One member of a mapping, a list of parameters is synchronised as a whole (e.g. X and Y of a
+/// position) and all members need the same number of them
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Endpoint {
+    pub device: String,
+    #[serde(deserialize_with = "one_or_many")]
+    pub param: Vec<String>,
+    /// Conversion of the values to the canonical value of the mapping and back
+    #[serde(default)]
+    pub transform: Transform,
+    /// Conversion of a pair of panner values to a position and back, after `transform`
+    pub coordinates: Option<Coordinates>,
+    /// WING, X32 or X-Air send of this member, overrides the one of the device
+    pub send: Option<u8>,
+    /// DS100 coordinate mapping of this member, overrides the one of the device
+    pub mapping_area: Option<u8>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(s: &str) -> Result<Self, ConfigError> {
+        let config: Config = toml::from_str(s)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        for master in [&self.startup.master, &self.resync.master] {
+            if let Some(master) = master
+                && !self.devices.contains_key(master)
+            {
+                return Err(ConfigError::UnknownMaster(master.clone()));
+            }
+        }
+
+        if self
+            .startup
+            .timeout
+            .is_some_and(|t| Duration::try_from_secs_f32(t).is_err())
+        {
+            return Err(ConfigError::InvalidTimeout("Startup".to_owned()));
+        }
+
+        if let Some(interval) = self.resync.interval
+            && interval != 0.0
+            && !Duration::try_from_secs_f32(interval).is_ok_and(|t| t >= MIN_RESYNC_INTERVAL)
+        {
+            return Err(ConfigError::InvalidInterval);
+        }
+
+        for (name, device) in &self.devices {
+            if !device.valid_banks(device.send, device.mapping_area) {
+                return Err(ConfigError::InvalidBank(format!("Device '{}'", name)));
+            }
+            if let Some(timeout) = device.timeout
+                && timeout != 0.0
+                && !Duration::try_from_secs_f32(timeout).is_ok_and(|t| t >= MIN_TIMEOUT)
+            {
+                return Err(ConfigError::InvalidTimeout(format!("Device '{}'", name)));
+            }
+            for rate in [device.poll_rate, device.send_rate] {
+                if rate.is_some_and(|v| v != 0.0 && !(MIN_RATE..f32::INFINITY).contains(&v)) {
+                    return Err(ConfigError::InvalidRate(name.clone()));
+                }
+            }
+        }
+
+        for mapping in &self.mappings {
+            if mapping.members.len() < 2 {
+                return Err(ConfigError::TooFewMembers(mapping.name.clone()));
+            }
+
+            for endpoint in &mapping.members {
+                let Some(device) = self.devices.get(&endpoint.device) else {
+                    return Err(ConfigError::UnknownDevice(
+                        mapping.name.clone(),
+                        endpoint.device.clone(),
+                    ));
+                };
+                if !device.valid_banks(endpoint.send, endpoint.mapping_area) {
+                    return Err(ConfigError::InvalidBank(format!(
+                        "Mapping '{}'",
+                        mapping.name
+                    )));
+                }
+            }
+
+            match (&mapping.authority, mapping.strategy) {
+                (None, ConflictStrategy::Authoritative) => {
+                    return Err(ConfigError::MissingAuthority(mapping.name.clone()));
+                }
+                (Some(authority), _) if !mapping.members.iter().any(|e| &e.device == authority) => {
+                    return Err(ConfigError::UnknownDevice(
+                        mapping.name.clone(),
+                        authority.clone(),
+                    ));
+                }
+                _ => {}
+            }
+
+            for value in [mapping.eps, mapping.force_eps, mapping.echo_eps] {
+                if value.is_some_and(|v| v < 0.0 || !v.is_finite()) {
+                    return Err(ConfigError::InvalidTolerance(mapping.name.clone()));
+                }
+            }
+            if mapping
+                .lockout
+                .is_some_and(|t| Duration::try_from_secs_f32(t).is_err())
+            {
+                return Err(ConfigError::InvalidTolerance(mapping.name.clone()));
+            }
+
+            let dimension = mapping.members[0].param.len();
+            if dimension == 0 || mapping.members.iter().any(|e| e.param.len() != dimension) {
+                return Err(ConfigError::DimensionMismatch(mapping.name.clone()));
+            }
+
+            for endpoint in &mapping.members {
+                let Some(coordinates) = &endpoint.coordinates else {
+                    continue;
+                };
+                if endpoint.param.len() != 2 {
+                    return Err(ConfigError::InvalidCoordinates(
+                        mapping.name.clone(),
+                        "they need exactly two parameters".to_owned(),
+                    ));
+                }
+                coordinates
+                    .validate()
+                    .map_err(|e| ConfigError::InvalidCoordinates(mapping.name.clone(), e))?;
+            }
+
+            if mapping.channels.from == 0 || mapping.channels.from > mapping.channels.to {
+                return Err(ConfigError::InvalidChannels(mapping.name.clone()));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::parse(DEFAULT_CONFIG).expect("Default configuration is invalid")
+    }
+}
+
+impl Startup {
+    pub fn timeout(&self) -> Option<Duration> {
+        match self.timeout {
+            None => Some(DEFAULT_STARTUP_TIMEOUT),
+            Some(t) if t > 0.0 => Some(Duration::from_secs_f32(t)),
+            Some(_) => None,
+        }
+    }
+}
+
+impl Resync {
+    pub fn interval(&self) -> Option<Duration> {
+        self.interval
+            .filter(|&t| t > 0.0)
+            .map(Duration::from_secs_f32)
+    }
+}
+
+impl DeviceConfig {
+    pub fn send_rate(&self) -> Option<f32> {
+        self.send_rate.filter(|&rate| rate > 0.0)
+    }
+
+    pub fn poll_rate(&self, profile: &dyn DeviceProfile) -> Option<f32> {
+        match self.poll_rate {
+            None => profile.default_poll_rate(),
+            Some(rate) if rate > 0.0 => Some(rate),
+            Some(_) => None,
+        }
+    }
+
+    /// Whether the device has the given send and coordinate mapping
+    fn valid_banks(&self, send: Option<u8>, mapping_area: Option<u8>) -> bool {
+        let valid = |value: Option<u8>, profiles: &[ProfileKind], max| {
+            value.is_none_or(|v| {
+                self.profile.is_some_and(|p| profiles.contains(&p)) && (1..=max).contains(&v)
+            })
+        };
+        // X-Air mixers have 6 buses and 4 effect sends
+        let sends = if self.profile == Some(ProfileKind::XAir) {
+            10
+        } else {
+            16
+        };
+
+        valid(
+            send,
+            &[ProfileKind::Wing, ProfileKind::X32, ProfileKind::XAir],
+            sends,
+        ) && valid(mapping_area, &[ProfileKind::Ds100], 4)
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        match self.timeout {
+            None => Some(DEFAULT_TIMEOUT),
+            Some(t) if t > 0.0 => Some(Duration::from_secs_f32(t)),
+            Some(_) => None,
+        }
+    }
+}
+
+impl Endpoint {
+    /// Send or coordinate mapping that the parameters of this member refer to, if it overrides
+    /// the one of the device
+    pub fn bank(&self) -> Option<usize> {
+        self.send.or(self.mapping_area).map(usize::from)
+    }
+}
+
+impl ChannelRange {
+    pub fn iter(&self) -> impl Iterator<Item = usize> + use<> {
+        self.from..=self.to
+    }
+}
+
+impl ParamConfig {
+    pub fn listen(&self) -> &str {
+        self.listen.as_deref().unwrap_or(&self.address)
+    }
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(param) => vec![param],
+        OneOrMany::Many(params) => params,
+    })
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Failed to read configuration file")]
+    Io(#[from] io::Error),
+    #[error("Failed to parse configuration file: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Mapping '{0}' refers to unknown device '{1}'")]
+    UnknownDevice(String, String),
+    #[error("Master '{0}' is not a configured device")]
+    UnknownMaster(String),
+    #[error("Mapping '{0}' uses the authoritative strategy but has no authority")]
+    MissingAuthority(String),
+    #[error("Mapping '{0}' has a negative, infinite or too long lockout or tolerance")]
+    InvalidTolerance(String),
+    #[error("Mapping '{0}' needs at least two members")]
+    TooFewMembers(String),
+    #[error("Mapping '{0}' needs the same number of parameters for all members")]
+    DimensionMismatch(String),
+    #[error("Mapping '{0}' has an invalid channel range")]
+    InvalidChannels(String),
+    #[error("Mapping '{0}' refers to unknown parameter '{2}' of device '{1}'")]
+    UnknownParam(String, String, String),
+    #[error("Device '{0}' has no profile and no port")]
+    MissingPort(String),
+    #[error("Mapping '{0}' has invalid coordinates, {1}")]
+    InvalidCoordinates(String, String),
+    #[error(
+        "{0} selects an invalid send or mapping area, WING and X32 sends are 1 to 16, X-Air sends 1 to 10 and DS100 mapping areas 1 to 4"
+    )]
+    InvalidBank(String),
+    #[error("{0} has a negative, too short or too long timeout")]
+    InvalidTimeout(String),
+    #[error("The resync interval has to be 0 or a finite number of at least 0.1 seconds")]
+    InvalidInterval,
+    #[error(
+        "Device '{0}' has an invalid poll or send rate, it has to be 0 or a finite number of at least 0.1 per second"
+    )]
+    InvalidRate(String),
+    #[error("Address of parameter '{1}' of device '{0}' has no {{n}} placeholder")]
+    InvalidTemplate(String, String),
+}
@@ -1,5 +1,177 @@
 mod ds100;
+mod wing;
 mod xair;
 
-pub use ds100::connect_ds100;
-pub use xair::discover_xair;
+pub use ds100::discover_ds100;
+pub use wing::discover_wing;
+pub use xair::{discover_x32, discover_xair};
+
+use get_if_addrs::{IfAddr, get_if_addrs};
+use rosc::{OscMessage, OscPacket, decoder::decode_udp as decode, encoder::encode};
+use std::{
+    fmt, io,
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    net::UdpSocket,
+    task::JoinSet,
+    time::{Instant, timeout_at},
+};
+
+// How long to wait for answers after sending a probe
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DeviceKind {
+    Ds100,
+    XAir,
+    X32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Discovered {
+    pub kind: DeviceKind,
+    pub addr: SocketAddr,
+    pub name: Option<String>,
+    /// Model and firmware version, if the device reports them
+    pub model: Option<String>,
+    pub firmware: Option<String>,
+}
+
+impl fmt::Display for DeviceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceKind::Ds100 => write!(f, "DS100"),
+            DeviceKind::XAir => write!(f, "X-Air"),
+            DeviceKind::X32 => write!(f, "X32"),
+        }
+    }
+}
+
+impl fmt::Display for Discovered {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}", self.kind, self.addr)?;
+
+        let details: Vec<String> = [
+            self.name.clone(),
+            self.model.clone(),
+            self.firmware.as_ref().map(|v| format!("firmware {}", v)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        if !details.is_empty() {
+            write!(f, " ({})", details.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Broadcast `msg` to `port` on every local interface and collect the devices that answer,
+/// `local_port` is the port the devices are expected to reply to
+async fn probe_osc<F>(msg: &OscMessage, port: u16, local_port: u16, parse: F) -> Vec<Discovered>
+where
+    F: Fn(SocketAddr, OscMessage) -> Option<Discovered> + Send + Sync + 'static,
+{
+    let buf = match encode(&OscPacket::Message(msg.clone())) {
+        Ok(buf) => buf,
+        Err(err) => {
+            log::error!("Failed to encode discovery message: {:?}", err);
+            return Vec::new();
+        }
+    };
+
+    probe_all(&buf, port, local_port, move |src, buf| match decode(buf) {
+        Ok((_, OscPacket::Message(msg))) => parse(src, msg),
+        Ok((_, OscPacket::Bundle(_))) => None,
+        Err(err) => {
+            log::debug!("Failed to decode discovery answer from {}: {:?}", src, err);
+            None
+        }
+    })
+    .await
+}
+
+/// Broadcast the raw `payload` to `port` on every local interface and collect the parsed
+/// answers, every sender is only reported once
+async fn probe_all<T, F>(payload: &[u8], port: u16, local_port: u16, parse: F) -> Vec<T>
+where
+    T: Send + 'static,
+    F: Fn(SocketAddr, &[u8]) -> Option<T> + Send + Sync + 'static,
+{
+    let if_addrs = match get_if_addrs() {
+        Ok(if_addrs) => if_addrs,
+        Err(err) => {
+            log::error!("Failed to list local network devices: {}", err);
+            return Vec::new();
+        }
+    };
+
+    let parse = Arc::new(parse);
+    let mut tasks = JoinSet::new();
+
+    for if_addr in if_addrs {
+        let IfAddr::V4(addr) = if_addr.addr else {
+            continue;
+        };
+        let Some(bc_addr) = addr.broadcast else {
+            continue;
+        };
+
+        let target = (bc_addr, port).into();
+        tasks.spawn(probe(
+            addr.ip,
+            local_port,
+            target,
+            payload.to_vec(),
+            parse.clone(),
+        ));
+    }
+
+    let mut found: Vec<(SocketAddr, T)> = Vec::new();
+
+    while let Some(res) = tasks.join_next().await {
+        match res {
+            Ok(Ok(answers)) => found.extend(answers),
+            Ok(Err(err)) => log::warn!("Discovery failed: {}", err),
+            Err(err) => log::warn!("Discovery task failed: {}", err),
+        }
+    }
+
+    found.sort_by_key(|(addr, _)| *addr);
+    found.dedup_by_key(|(addr, _)| *addr);
+    found.into_iter().map(|(_, t)| t).collect()
+}
+
+async fn probe<T, F>(
+    ip: Ipv4Addr,
+    local_port: u16,
+    target: SocketAddr,
+    payload: Vec<u8>,
+    parse: Arc<F>,
+) -> io::Result<Vec<(SocketAddr, T)>>
+where
+    F: Fn(SocketAddr, &[u8]) -> Option<T>,
+{
+    let sock = UdpSocket::bind((ip, local_port)).await?;
+    sock.set_broadcast(true)?;
+
+    log::info!("Sending discovery broadcast from {} to {}", ip, target);
+    sock.send_to(&payload, target).await?;
+
+    let deadline = Instant::now() + DISCOVERY_TIMEOUT;
+    let mut found = Vec::new();
+    let mut buf = vec![0; 1024];
+
+    while let Ok(Ok((len, src))) = timeout_at(deadline, sock.recv_from(&mut buf)).await {
+        log::debug!("Discovery answer from {}: {:?}", src, &buf[..len]);
+        if let Some(t) = parse(src, &buf[..len]) {
+            found.push((src, t));
+        }
+    }
+
+    Ok(found)
+}
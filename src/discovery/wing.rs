@@ -0,0 +1,60 @@
+use super::probe_all;
+use std::{fmt, net::Ipv4Addr};
+
+const PORT: u16 = 2222;
+
+/// Answer of a WING to a discovery request
+#[derive(Clone, Debug)]
+pub struct WingInfo {
+    pub ip: Ipv4Addr,
+    pub name: String,
+    pub model: String,
+    pub serial: String,
+    pub firmware: String,
+}
+
+/// Send a `WING?` broadcast to find all WING consoles on the local networks
+pub async fn discover_wing() -> Vec<WingInfo> {
+    probe_all(b"WING?", PORT, 0, |src, buf| {
+        let info = parse(buf);
+        if info.is_none() {
+            log::warn!("Unexpected answer to WING discovery from {}", src);
+        }
+        info
+    })
+    .await
+}
+
+impl WingInfo {
+    /// Whether `select` is the name or the serial number of this console
+    pub fn matches(&self, select: &str) -> bool {
+        self.name == select || self.serial == select
+    }
+}
+
+impl fmt::Display for WingInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "WING at {} ({}, {}, serial {}, firmware {})",
+            self.ip, self.name, self.model, self.serial, self.firmware
+        )
+    }
+}
+
+// The answer has the form "WING,<ip>,<name>,<model>,<serial>,<firmware>"
+fn parse(buf: &[u8]) -> Option<WingInfo> {
+    let s = std::str::from_utf8(buf).ok()?.trim_end_matches('\0');
+    let parts: Vec<&str> = s.split(',').collect();
+
+    match parts.as_slice() {
+        ["WING", ip, name, model, serial, firmware] => Some(WingInfo {
+            ip: ip.parse().ok()?,
+            name: name.to_string(),
+            model: model.to_string(),
+            serial: serial.to_string(),
+            firmware: firmware.to_string(),
+        }),
+        _ => None,
+    }
+}
@@ -1,63 +1,73 @@
-use crate::osc_device::OscDevice;
-use futures::stream::{FuturesUnordered, Stream, StreamExt};
-use get_if_addrs::{get_if_addrs, IfAddr};
-use rosc::decoder;
-use std::io;
-use std::net::Ipv4Addr;
-use tokio::net::UdpSocket;
-
-pub fn discover_xair() -> impl Stream<Item = OscDevice> {
-    let futures = FuturesUnordered::new();
-
-    if let Ok(if_addrs) = get_if_addrs() {
-        let bc_addrs = if_addrs
-            .iter()
-            .filter_map(|if_addr| match if_addr.addr.clone() {
-                IfAddr::V4(addr) => {
-                    if let Some(bc) = addr.broadcast {
-                        Some((addr.ip, bc))
-                    } else {
-                        None
-                    }
-                }
-                _ => None,
-            });
-
-        for (ip, bc_addr) in bc_addrs {
-            futures.push(tokio::spawn(request_initial_info(ip, bc_addr)));
-        }
-    }
+use super::{DeviceKind, Discovered, probe_osc};
+use rosc::{OscMessage, OscType};
 
-    futures.filter_map(|f| async move {
-        match f {
-            Ok(Ok(x)) => Some(x),
-            _ => None,
-        }
-    })
-}
+// X-Air and X32 mixers answer the same request on different ports
+const XAIR_PORT: u16 = 10024;
+const X32_PORT: u16 = 10023;
 
-async fn request_initial_info(addr: Ipv4Addr, bc_addr: Ipv4Addr) -> Result<OscDevice, io::Error> {
-    let port = 10024; // 10023 for X32
+const XINFO: &str = "/xinfo";
 
-    let buf = b"/xinfo";
+/// Answer to `/xinfo`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct XInfo {
+    pub name: String,
+    pub model: String,
+    pub firmware: String,
+}
 
-    let mut socket: UdpSocket = UdpSocket::bind((addr, 0)).await?;
-    log::info!("Listening on {}", socket.local_addr().unwrap());
+impl XInfo {
+    /// Parse an `/xinfo` answer, its arguments are the IP, name, model and firmware version
+    pub fn parse(msg: &OscMessage) -> Option<Self> {
+        if msg.addr != XINFO {
+            return None;
+        }
 
-    socket.set_broadcast(true)?;
+        let string = |i: usize| match msg.args.get(i) {
+            Some(OscType::String(s)) => Some(s.clone()),
+            _ => None,
+        };
 
-    log::info!("Sending /xinfo broadcast to {}:{}", bc_addr, port);
+        Some(XInfo {
+            name: string(1)?,
+            model: string(2)?,
+            firmware: string(3)?,
+        })
+    }
+}
+
+/// Send an `/xinfo` broadcast to find all X-Air mixers on the local networks
+pub async fn discover_xair() -> Vec<Discovered> {
+    discover_xinfo(XAIR_PORT, DeviceKind::XAir).await
+}
 
-    socket.send_to(buf, (bc_addr, port)).await?;
+/// Send an `/xinfo` broadcast to find all X32 and M32 consoles on the local networks
+pub async fn discover_x32() -> Vec<Discovered> {
+    discover_xinfo(X32_PORT, DeviceKind::X32).await
+}
 
-    let mut buf = vec![0; 256];
-    let (_, src) = socket.recv_from(&mut buf).await?;
+async fn discover_xinfo(port: u16, kind: DeviceKind) -> Vec<Discovered> {
+    let msg = OscMessage {
+        addr: XINFO.to_owned(),
+        args: Vec::new(),
+    };
 
-    let res = decoder::decode(&buf).unwrap();
-    log::info!("Message: {:?}", res);
+    probe_osc(&msg, port, 0, move |src, msg| {
+        if msg.addr != XINFO {
+            return None;
+        }
 
-    socket.connect(src).await?;
-    socket.set_broadcast(false)?;
+        let info = XInfo::parse(&msg);
+        if info.is_none() {
+            log::debug!("Incomplete /xinfo answer from {}: {:?}", src, msg.args);
+        }
 
-    Ok(OscDevice::new(socket, src))
+        Some(Discovered {
+            kind,
+            addr: src,
+            name: info.as_ref().map(|i| i.name.clone()),
+            model: info.as_ref().map(|i| i.model.clone()),
+            firmware: info.map(|i| i.firmware),
+        })
+    })
+    .await
 }
@@ -1,29 +1,45 @@
+mod bridge;
+mod config;
+mod discovery;
 mod osc_device;
+mod poller;
+mod profile;
+mod rate;
 mod sync;
+mod transform;
+mod value;
 
+use bridge::Bridge;
+use config::{Config, ProfileKind};
 use get_if_addrs::{IfAddr, Interface, get_if_addrs};
 use ipnetwork::Ipv4Network;
 use osc_device::OscDevice;
-use regex::{Regex, RegexSet};
-use sync::{Side, Sync};
 
-use rosc::{OscMessage, OscType};
 use std::{
     net::{IpAddr, Ipv4Addr},
-    time::Duration,
+    path::PathBuf,
 };
 use structopt::StructOpt;
 
-const MAIN_DELTA: Duration = Duration::from_millis(100);
-
 #[derive(StructOpt)]
 struct Cli {
+    /// Mapping configuration, defaults to the built-in WING <-> DS100 mapping
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+    /// Overrides the IP of the device "wing"
+    #[structopt(long)]
+    wing_ip: Option<Ipv4Addr>,
+    /// Name or serial number of the WING to use if several are discovered
+    #[structopt(long)]
+    wing_select: Option<String>,
+    /// Overrides the IP of the device "ds100"
     #[structopt(long)]
-    wing_ip: Ipv4Addr,
+    ds100_ip: Option<Ipv4Addr>,
     #[structopt(long)]
-    ds100_ip: Ipv4Addr,
+    monitor: Vec<usize>,
+    /// List the devices found on the local networks and exit
     #[structopt(long)]
-    monitor: Vec<i32>,
+    discover: bool,
 }
 
 fn main() {
@@ -35,241 +51,156 @@ fn main() {
 
     let args = Cli::from_args();
 
-    let if_addrs = get_if_addrs().expect("Failed to list local network devices");
-
-    let ds100_ip = IpAddr::V4(args.ds100_ip);
-    let wing_ip = IpAddr::V4(args.wing_ip);
-
-    let ds100_local = get_matching_interface(ds100_ip, &if_addrs)
-        .expect("Failed to find matching local interface");
-    let wing_local = get_matching_interface(wing_ip, &if_addrs)
-        .expect("Failed to find matching local interface");
-
-    log::info!("Connecting to DS100...");
-    let ds100 = OscDevice::new("DS100", (ds100_ip, 50010), (ds100_local, 50011))
-        .expect("Failed to create UDP socket for DS100");
-    log::info!("Connecting to WING...");
-    let wing = OscDevice::new("WING", (wing_ip, 2223), (wing_local, 0))
-        .expect("Failed to create UDP socket for WING");
-
-    let ds100_regex_set = RegexSet::new([
-        r"^/dbaudio1/coordinatemapping/source_position_xy/1/",
-        r"^/dbaudio1/matrixinput/reverbsendgain/",
-        r"^/dbaudio1/reverbinputprocessing/gain/",
-    ])
-    .unwrap();
-
-    let wing_channel_regex = Regex::new(r"^/ch/(\d+)/send/1/(pan|wid|lvl)$").unwrap();
-
-    let wing_bus_regex = Regex::new(r"^/bus/(\d+)/fdr$").unwrap();
-
-    let mut x_positions = Vec::new();
-    let mut y_positions = Vec::new();
-    let mut gains = Vec::new();
-
-    for i in 1..=40 {
-        x_positions.push(Sync::new(format!("x{:02}", i)));
-        y_positions.push(Sync::new(format!("y{:02}", i)));
-        gains.push(Sync::with_transform(
-            format!("g{:02}", i),
-            gain_ds100_to_wing,
-            gain_wing_to_ds100,
-        ));
-    }
-
-    let mut reverb_gains = Vec::new();
+    tokio::runtime::Runtime::new()
+        .expect("Failed to start async runtime")
+        .block_on(run(args));
+}
 
-    for i in 1..=4 {
-        reverb_gains.push(Sync::with_transform(
-            format!("rg{}", i),
-            gain_ds100_to_wing,
-            gain_wing_to_ds100,
-        ));
+async fn run(args: Cli) {
+    if args.discover {
+        for wing in discovery::discover_wing().await {
+            println!("{}", wing);
+        }
+        for device in discovery::discover_ds100().await {
+            println!("{}", device);
+        }
+        for device in discovery::discover_xair().await {
+            println!("{}", device);
+        }
+        for device in discovery::discover_x32().await {
+            println!("{}", device);
+        }
+        return;
     }
 
-    subscribe_wing(&wing);
-    subscribe_ds100(&ds100);
-
-    loop {
-        std::thread::sleep(MAIN_DELTA);
-
-        for msg in ds100.flush() {
-            log::debug!("Got DS100 message {:?}", msg);
-            let ds100_matches = ds100_regex_set.matches(&msg.addr);
+    let config = match &args.config {
+        Some(path) => Config::load(path).expect("Failed to load configuration"),
+        None => Config::default(),
+    };
 
-            if ds100_matches.matched_any() {
-                let n: usize = msg.addr.rsplit('/').next().unwrap().parse().unwrap();
-
-                match ds100_matches.iter().next().unwrap() {
-                    0 => {
-                        if let OscType::Float(x) = msg.args[0] {
-                            x_positions[n - 1].update(Side::Left, x);
-                        }
-                        if let OscType::Float(y) = msg.args[1] {
-                            y_positions[n - 1].update(Side::Left, y);
-                        }
-                    }
-
-                    1 => {
-                        if let OscType::Float(gain) = msg.args[0] {
-                            gains[n - 1].update(Side::Left, gain);
-                        }
-                    }
+    let if_addrs = get_if_addrs().expect("Failed to list local network devices");
 
-                    2 => {
-                        if let OscType::Float(revgain) = msg.args[0] {
-                            reverb_gains[n - 1].update(Side::Left, revgain);
-                        }
-                    }
+    let mut devices = Vec::new();
 
-                    _ => {}
-                }
-            }
+    for (name, device) in &config.devices {
+        let ip = match name.as_str() {
+            "wing" => args.wing_ip,
+            "ds100" => args.ds100_ip,
+            _ => None,
         }
+        .or(device.ip);
+
+        let ip = match ip {
+            Some(ip) => ip,
+            None => discover_ip(name, device.profile, args.wing_select.as_deref())
+                .await
+                .unwrap_or_else(|| panic!("No IP given for device '{}'", name)),
+        };
+
+        let ip = IpAddr::V4(ip);
+        let local =
+            get_matching_interface(ip, &if_addrs).expect("Failed to find matching local interface");
+
+        let profile = profile::create(name, device).expect("Invalid device configuration");
+        let port = device.port.unwrap_or(profile.default_port());
+        let local_port = device.local_port.unwrap_or(profile.default_local_port());
+
+        log::info!("Connecting to {}...", name);
+        let options = osc_device::Options {
+            bundle: device.bundle,
+            timeout: device.timeout(),
+            send_rate: device.send_rate(),
+        };
+        let osc = OscDevice::new(name, (ip, port), (local, local_port), options)
+            .await
+            .expect("Failed to create UDP socket");
+        devices.push((name.clone(), osc, profile));
+    }
 
-        for msg in wing.flush() {
-            log::debug!("Got WING message {:?}", msg);
-            if let Some(cap) = wing_channel_regex.captures(&msg.addr) {
-                // map channel input
-
-                let n: usize = cap[1].parse().unwrap();
+    let mut bridge = Bridge::new(&config, devices).expect("Invalid mapping configuration");
 
-                match &cap[2] {
-                    "pan" => {
-                        if let OscType::Float(x) = msg.args[2] {
-                            x_positions[n - 1].update(Side::Right, x);
-                        }
-                    }
-                    "wid" => {
-                        if let OscType::Float(x) = msg.args[2] {
-                            y_positions[n - 1].update(Side::Right, x);
-                        }
-                    }
-                    "lvl" => {
-                        if let OscType::Float(x) = msg.args[2] {
-                            gains[n - 1].update(Side::Right, x);
-                        }
-                    }
-                    _ => {}
-                }
+    tokio::select! {
+        _ = bridge.run(&args.monitor) => {}
+        _ = shutdown_signal() => {
+            log::info!("Shutting down...");
+        }
+    }
 
-                continue;
-            }
+    bridge.close().await;
+}
 
-            if let Some(cap) = wing_bus_regex.captures(&msg.addr) {
-                // map channel input
-                let n: usize = cap[1].parse().unwrap();
-                if let OscType::Float(x) = msg.args[2] {
-                    reverb_gains[n - 1].update(Side::Right, x);
-                }
+/// Wait for SIGINT or SIGTERM
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
 
-                continue;
-            }
-        }
+        let mut terminate =
+            signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
 
-        for i in args.monitor.iter() {
-            let n: usize = *i as usize - 1;
-            let x_sync = &x_positions[n];
-            let y_sync = &y_positions[n];
-            let gain = &gains[n];
-            log::info!(
-                "Channel {}:\tDS100 ({}, {}) @ {}\tWING ({}, {}) @ {}\tMaster: {:?}, {:?}, {:?}",
-                n,
-                x_sync.left_value() as i32,
-                y_sync.left_value() as i32,
-                gain.left_value() as i32,
-                x_sync.right_value() as i32,
-                y_sync.right_value() as i32,
-                gain.right_value() as i32,
-                x_sync.current_master(),
-                y_sync.current_master(),
-                gain.current_master()
-            );
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
         }
+    }
 
-        for i in 0..40 {
-            let n = i + 1;
-
-            match x_positions[i].flush() {
-                Some((value, Side::Left)) => {
-                    let addr = format!("/dbaudio1/coordinatemapping/source_position_x/1/{}", n);
-                    let args = vec![OscType::Float(value)];
-                    log::info!(
-                        "Sending {:?}",
-                        OscMessage {
-                            addr: addr.clone(),
-                            args: args.clone()
-                        }
-                    );
-                    ds100.send(OscMessage { addr, args });
-                }
-                Some((value, Side::Right)) => {
-                    let addr = format!("/ch/{}/send/1/pan", n);
-                    let args = vec![OscType::Float(value)];
-                    log::info!(
-                        "Sending {:?}",
-                        OscMessage {
-                            addr: addr.clone(),
-                            args: args.clone()
-                        }
-                    );
-                    wing.send(OscMessage { addr, args });
-                }
-                _ => {}
-            }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Failed to install Ctrl-C handler");
+}
 
-            // if i == 0 { break; }
+async fn discover_ip(
+    name: &str,
+    profile: Option<ProfileKind>,
+    wing_select: Option<&str>,
+) -> Option<Ipv4Addr> {
+    let found = match profile? {
+        ProfileKind::Ds100 => discovery::discover_ds100().await,
+        ProfileKind::X32 => discovery::discover_x32().await,
+        ProfileKind::XAir => discovery::discover_xair().await,
+        ProfileKind::Wing => return discover_wing_ip(name, wing_select).await,
+    };
+
+    if found.len() > 1 {
+        log::warn!(
+            "Found {} candidates for {}, using the first one",
+            found.len(),
+            name
+        );
+    }
 
-            match y_positions[i].flush() {
-                Some((value, Side::Left)) => {
-                    let addr = format!("/dbaudio1/coordinatemapping/source_position_y/1/{}", n);
-                    let args = vec![OscType::Float(value)];
-                    ds100.send(OscMessage { addr, args });
-                }
-                Some((value, Side::Right)) => {
-                    let addr = format!("/ch/{}/send/1/wid", n);
-                    let args = vec![OscType::Float(value)];
-                    wing.send(OscMessage { addr, args });
-                }
-                _ => {}
-            }
+    let device = found.into_iter().next()?;
+    log::info!("Discovered {}: {}", name, device);
 
-            match gains[i].flush() {
-                Some((value, Side::Left)) => {
-                    let addr = format!("/dbaudio1/matrixinput/reverbsendgain/{}", n);
-                    let args = vec![OscType::Float(value)];
-                    ds100.send(OscMessage { addr, args });
-                }
-                Some((value, Side::Right)) => {
-                    let addr = format!("/ch/{}/send/1/lvl", n);
-                    let args = vec![OscType::Float(value)];
-                    wing.send(OscMessage { addr, args });
-                }
-                _ => {}
-            }
-        }
+    match device.addr.ip() {
+        IpAddr::V4(ip) => Some(ip),
+        IpAddr::V6(_) => None,
+    }
+}
 
-        for (i, reverb_gain) in reverb_gains.iter_mut().take(4).enumerate() {
-            let n = i + 1;
-            match reverb_gain.flush() {
-                Some((value, Side::Left)) => {
-                    let addr = format!("/dbaudio1/reverbinputprocessing/gain/{n}");
-                    let args = vec![OscType::Float(value)];
-                    ds100.send(OscMessage { addr, args });
-                }
-                Some((value, Side::Right)) => {
-                    let addr = format!("/bus/{}/fdr", n);
-                    let args = vec![OscType::Float(value)];
-                    wing.send(OscMessage { addr, args });
-                }
-                _ => {}
-            }
+async fn discover_wing_ip(name: &str, select: Option<&str>) -> Option<Ipv4Addr> {
+    let found: Vec<_> = discovery::discover_wing()
+        .await
+        .into_iter()
+        .filter(|wing| select.is_none_or(|select| wing.matches(select)))
+        .collect();
+
+    if found.len() > 1 {
+        log::error!(
+            "Found {} WING consoles for {}, select one by name or serial with --wing-select:",
+            found.len(),
+            name
+        );
+        for wing in &found {
+            log::error!("  {}", wing);
         }
-
-        // Send new settings
-        subscribe_wing(&wing);
-        subscribe_ds100(&ds100);
+        return None;
     }
+
+    let wing = found.into_iter().next()?;
+    log::info!("Discovered {}: {}", name, wing);
+    Some(wing.ip)
 }
 
 fn get_matching_interface(addr: IpAddr, interfaces: &[Interface]) -> Option<IpAddr> {
@@ -298,54 +229,3 @@ fn get_matching_interface(addr: IpAddr, interfaces: &[Interface]) -> Option<IpAd
     log::error!("No matching local interface found for {}", addr);
     None
 }
-
-fn empty_msg(addr: String) -> OscMessage {
-    OscMessage {
-        addr,
-        args: Vec::new(),
-    }
-}
-
-fn subscribe_ds100(device: &OscDevice) {
-    for i in 1..=40 {
-        let addr = format!("/dbaudio1/matrixinput/reverbsendgain/{}", i);
-        device.send(empty_msg(addr));
-        let addr = format!("/dbaudio1/coordinatemapping/source_position_xy/1/{}", i);
-        device.send(empty_msg(addr));
-    }
-
-    for i in 1..=4 {
-        let addr = format!("/dbaudio1/reverbinputprocessing/gain/{}", i);
-        device.send(empty_msg(addr));
-    }
-}
-
-fn subscribe_wing(device: &OscDevice) {
-    for i in 1..=40 {
-        for suffix in &["pan", "wid", "lvl"] {
-            let addr = format!("/ch/{}/send/1/{}", i, suffix);
-            device.send(empty_msg(addr));
-        }
-    }
-
-    for i in 1..=4 {
-        let addr = format!("/bus/{}/fdr", i);
-        device.send(empty_msg(addr));
-    }
-}
-
-fn gain_wing_to_ds100(val: f32) -> f32 {
-    if val > 0.0 {
-        val / 10.0 * 24.0
-    } else {
-        val / 144.0 * 120.0
-    }
-}
-
-fn gain_ds100_to_wing(val: f32) -> f32 {
-    if val > 0.0 {
-        val / 24.0 * 10.0
-    } else {
-        val / 120.0 * 144.0
-    }
-}
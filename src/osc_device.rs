@@ -1,123 +1,523 @@
-use rosc::{OscMessage, OscPacket, decoder::decode_udp as decode, encoder::encode};
+use crate::rate::RateLimit;
+use rosc::{
+    OscBundle, OscMessage, OscPacket, OscTime, decoder::decode_udp as decode, encoder::encode,
+};
 use std::{
+    collections::VecDeque,
     io,
-    net::{SocketAddr, UdpSocket},
-    sync::mpsc::{Receiver, Sender, channel},
-    thread,
-    time::Duration,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, SystemTime},
 };
 use thiserror::Error;
-use thread::JoinHandle;
+use tokio::{
+    net::UdpSocket,
+    sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
+    task::JoinHandle,
+    time::{Instant, MissedTickBehavior, interval, sleep_until},
+};
 
 const BUF_SIZE: usize = 65535;
 
+// Maximum size of an outgoing bundle, chosen to fit into a single Ethernet frame
+const MAX_BUNDLE_SIZE: usize = 1472;
+
+// Time tag that marks bundle contents for immediate processing
+const IMMEDIATELY: OscTime = OscTime {
+    seconds: 0,
+    fractional: 1,
+};
+
+// Outgoing messages that are kept while waiting for the rate limit, the oldest ones are dropped
+const MAX_QUEUE: usize = 1024;
+
+// Bounds for the time between reconnection attempts, doubled on every failure
+const MIN_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Send all messages that are queued at the same time as bundles
+    pub bundle: bool,
+    /// The device counts as offline if nothing has been received for this long
+    pub timeout: Option<Duration>,
+    /// Messages sent per second at most
+    pub send_rate: Option<f32>,
+}
+
+/// Connection health of a device
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    pub online: bool,
+    pub last_received: Option<Instant>,
+    pub received: u64,
+    pub sent: u64,
+    pub send_errors: u64,
+    /// Messages that were replaced by a newer one to the same address before they were sent
+    pub coalesced: u64,
+    /// Messages that were discarded because the queue was full or the device was unreachable
+    pub dropped: u64,
+}
+
+#[derive(Debug)]
+pub enum Event {
+    Message(OscMessage),
+    /// The device answered for the first time or after a failure
+    Online,
+    /// Sending to the device failed or it has not answered within the timeout
+    Offline,
+}
+
+/// Handle to the task that talks to a single device, dropping it stops the task after all
+/// queued messages have been sent. Messages to an address that is already queued replace the
+/// queued one.
 pub struct OscDevice {
-    _thread: JoinHandle<()>,
-    send: Sender<OscMessage>,
-    recv: Receiver<OscMessage>,
+    name: String,
+    task: JoinHandle<()>,
+    send: UnboundedSender<OscMessage>,
+    recv: UnboundedReceiver<Event>,
+    stats: Arc<Mutex<Stats>>,
 }
 
 impl OscDevice {
-    pub fn new(
+    pub async fn new(
         name: &str,
         send_addr: impl Into<SocketAddr>,
         recv_addr: impl Into<SocketAddr>,
+        options: Options,
     ) -> Result<Self, OscDeviceError> {
         let send_addr = send_addr.into();
         let recv_addr = recv_addr.into();
 
         let name = name.to_owned();
 
-        create_thread(name.clone(), send_addr, recv_addr)
+        create_task(name, send_addr, recv_addr, options).await
     }
 
-    pub fn send(&self, msg: OscMessage) {
-        self.send.send(msg).expect("Failed to send msg");
+    pub fn send(&self, msg: OscMessage) -> Result<(), OscDeviceError> {
+        self.send.send(msg).map_err(|_| OscDeviceError::Closed)
     }
 
-    pub fn flush(&self) -> Vec<OscMessage> {
-        self.recv.try_iter().collect()
+    /// Whether the task has stopped, no further messages can be sent or received
+    pub fn is_closed(&self) -> bool {
+        self.send.is_closed()
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats.lock().unwrap().clone()
+    }
+
+    /// Send all queued messages and wait for the task to stop
+    pub async fn close(self) {
+        let OscDevice {
+            name,
+            task,
+            send,
+            recv,
+            ..
+        } = self;
+
+        drop(send);
+
+        if let Err(err) = task.await {
+            log::error!("[{}] Task failed: {}", name, err);
+        }
+
+        drop(recv);
+        log::info!("[{}] Closed", name);
+    }
+
+    /// Poll for the next message or connection change of the device
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        self.recv.poll_recv(cx)
     }
 }
 
-fn create_thread(
+async fn create_task(
     name: String,
     send_addr: SocketAddr,
     recv_addr: SocketAddr,
+    options: Options,
 ) -> Result<OscDevice, OscDeviceError> {
-    let sock = UdpSocket::bind(recv_addr)?;
-    sock.connect(send_addr)?;
-    sock.set_read_timeout(Some(Duration::from_millis(1)))?;
+    let sock = connect(send_addr, recv_addr).await?;
+
+    let (tx_send, rx_send) = unbounded_channel();
+    let (tx_recv, rx_recv) = unbounded_channel();
+    let stats = Arc::new(Mutex::new(Stats::default()));
+
+    let worker = Worker {
+        name: name.clone(),
+        send_addr,
+        recv_addr,
+        limit: options.send_rate.map(RateLimit::new),
+        options,
+        queue: VecDeque::new(),
+        rx_send,
+        tx_recv,
+        stats: stats.clone(),
+        backoff: MIN_BACKOFF,
+    };
+    let task = tokio::spawn(worker.run(sock));
+
+    Ok(OscDevice {
+        name,
+        task,
+        send: tx_send,
+        recv: rx_recv,
+        stats,
+    })
+}
+
+async fn connect(send_addr: SocketAddr, recv_addr: SocketAddr) -> io::Result<UdpSocket> {
+    let sock = UdpSocket::bind(recv_addr).await?;
+    sock.connect(send_addr).await?;
     log::info!("Awaiting messages from {}", send_addr);
-    log::info!("Listening on {}", sock.local_addr().unwrap());
+    log::info!("Listening on {}", sock.local_addr()?);
+    Ok(sock)
+}
+
+struct Worker {
+    name: String,
+    send_addr: SocketAddr,
+    recv_addr: SocketAddr,
+    options: Options,
+    // Messages waiting to be sent, at most one per address
+    queue: VecDeque<OscMessage>,
+    limit: Option<RateLimit>,
+    rx_send: UnboundedReceiver<OscMessage>,
+    tx_recv: UnboundedSender<Event>,
+    stats: Arc<Mutex<Stats>>,
+    backoff: Duration,
+}
+
+impl Worker {
+    async fn run(mut self, mut sock: UdpSocket) {
+        loop {
+            match self.serve(&sock).await {
+                Ok(()) => return,
+                Err(err) => self.set_offline(&err),
+            }
+
+            self.stats.lock().unwrap().dropped += self.queue.len() as u64;
+            self.queue.clear();
 
-    let (tx_send, rx_send) = channel();
-    let (tx_recv, rx_recv) = channel();
+            drop(sock);
 
-    let thr = thread::spawn(move || {
+            sock = loop {
+                log::debug!("[{}] Reconnecting in {:?}", self.name, self.backoff);
+                if !self.discard_for(self.backoff).await {
+                    return;
+                }
+                self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+
+                match connect(self.send_addr, self.recv_addr).await {
+                    Ok(sock) => break sock,
+                    Err(err) => log::warn!("[{}] Failed to reconnect: {}", self.name, err),
+                }
+            };
+        }
+    }
+
+    /// Forward messages in both directions until all handles have been dropped (`Ok`) or the
+    /// socket fails
+    async fn serve(&mut self, sock: &UdpSocket) -> io::Result<()> {
         let mut buf = vec![0; BUF_SIZE];
 
+        let timeout = self.options.timeout;
+        let mut liveness = interval(timeout.map_or(Duration::from_secs(1), |t| t / 2));
+        liveness.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
         loop {
-            while let Ok(len) = sock.recv(&mut buf) {
-                if !handle_receive(&name, &buf[..len], &tx_recv) {
-                    break;
-                };
+            let next_send = match &mut self.limit {
+                Some(limit) => limit.next_available(),
+                None => Instant::now(),
+            };
+
+            tokio::select! {
+                res = sock.recv(&mut buf) => {
+                    let len = res?;
+                    self.set_online();
+                    {
+                        let mut stats = self.stats.lock().unwrap();
+                        stats.received += 1;
+                        stats.last_received = Some(Instant::now());
+                    }
+                    if !handle_receive(&self.name, &buf[..len], &self.tx_recv) {
+                        return Ok(());
+                    }
+                }
+                msg = self.rx_send.recv() => {
+                    // All handles have been dropped
+                    let Some(msg) = msg else {
+                        return self.send_queued(sock, true).await;
+                    };
+
+                    self.enqueue(msg);
+                    while let Ok(msg) = self.rx_send.try_recv() {
+                        self.enqueue(msg);
+                    }
+                }
+                _ = sleep_until(next_send), if !self.queue.is_empty() => {
+                    self.send_queued(sock, false).await?;
+                }
+                _ = liveness.tick(), if timeout.is_some() => {
+                    self.check_liveness();
+                }
             }
+        }
+    }
+
+    /// Queue `msg`, replacing a queued message to the same address. Queries (without arguments)
+    /// and updates are kept apart, so that neither gets lost.
+    fn enqueue(&mut self, msg: OscMessage) {
+        let mut stats = self.stats.lock().unwrap();
+
+        if let Some(queued) = self
+            .queue
+            .iter_mut()
+            .find(|m| m.addr == msg.addr && m.args.is_empty() == msg.args.is_empty())
+        {
+            *queued = msg;
+            stats.coalesced += 1;
+            return;
+        }
+
+        if self.queue.len() >= MAX_QUEUE {
+            self.queue.pop_front();
+            stats.dropped += 1;
+        }
+        self.queue.push_back(msg);
+    }
+
+    /// Send as many queued messages as the rate limit allows, or all of them
+    async fn send_queued(&mut self, sock: &UdpSocket, all: bool) -> io::Result<()> {
+        let count = match &mut self.limit {
+            Some(limit) if !all => limit.take(self.queue.len()),
+            _ => self.queue.len(),
+        };
+        if count == 0 {
+            return Ok(());
+        }
+
+        let msgs: Vec<OscMessage> = self.queue.drain(..count).collect();
 
-            // TODO: Check if rx_send is still valid by doing a single peek
-            /* match rx_send.recv_timeout(Duration::from_millis(1)) {
-                Ok(_) => {}
-                Err(_) => {}
-            } */
-            for msg in rx_send.try_iter() {
-                log::debug!("Sending message {:?}", msg);
-                handle_send(&name, &sock, msg);
+        let res = if self.options.bundle {
+            log::debug!("Sending {} messages as bundles", msgs.len());
+            handle_send_bundled(&self.name, sock, msgs).await
+        } else {
+            handle_send_each(&self.name, sock, msgs).await
+        };
+
+        let mut stats = self.stats.lock().unwrap();
+        match res {
+            Ok(()) => stats.sent += count as u64,
+            Err(err) => {
+                stats.send_errors += 1;
+                log::warn!(
+                    "[{}] Failed to send packet: {} ({} errors so far)",
+                    self.name,
+                    err,
+                    stats.send_errors
+                );
+                return Err(err);
             }
+        }
+
+        Ok(())
+    }
 
-            std::thread::sleep(Duration::from_millis(1));
+    fn check_liveness(&mut self) {
+        let Some(timeout) = self.options.timeout else {
+            return;
+        };
+
+        let stats = self.stats.lock().unwrap();
+        if !stats.online || stats.last_received.is_some_and(|t| t.elapsed() < timeout) {
+            return;
         }
-    });
+        drop(stats);
 
-    Ok(OscDevice {
-        _thread: thr,
-        send: tx_send,
-        recv: rx_recv,
-    })
-}
+        self.set_offline(&format!("Nothing received for {:?}", timeout));
+    }
 
-fn handle_receive(name: &str, buf: &[u8], tx: &Sender<OscMessage>) -> bool {
-    match decode(buf) {
-        Ok((_, OscPacket::Message(msg))) => {
-            if tx.send(msg).is_err() {
-                log::info!("Failed to forward message, stopping thread");
-                return false;
+    /// Drop all outgoing messages for the given duration, returns `false` if all handles have
+    /// been dropped in the meantime
+    async fn discard_for(&mut self, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+
+        loop {
+            tokio::select! {
+                _ = sleep_until(deadline) => return true,
+                msg = self.rx_send.recv() => {
+                    if msg.is_none() {
+                        return false;
+                    }
+                    self.stats.lock().unwrap().dropped += 1;
+                }
             }
         }
-        Ok((_, OscPacket::Bundle(bdl))) => {
-            log::error!("Received unexpected bundle: {bdl:?}");
+    }
+
+    fn set_online(&mut self) {
+        let mut stats = self.stats.lock().unwrap();
+        if !stats.online {
+            log::info!("[{}] Device is online", self.name);
+            stats.online = true;
+            self.backoff = MIN_BACKOFF;
+            let _ = self.tx_recv.send(Event::Online);
+        }
+    }
+
+    fn set_offline(&mut self, reason: &dyn std::fmt::Display) {
+        let mut stats = self.stats.lock().unwrap();
+        if stats.online {
+            log::warn!("[{}] Device is offline: {}", self.name, reason);
+            stats.online = false;
+            let _ = self.tx_recv.send(Event::Offline);
+        } else {
+            log::debug!("[{}] Device is still offline: {}", self.name, reason);
         }
+    }
+}
+
+fn handle_receive(name: &str, buf: &[u8], tx: &UnboundedSender<Event>) -> bool {
+    let packet = match decode(buf) {
+        Ok((_, packet)) => packet,
         Err(err) => {
             log::error!("[{name}] Failed to decode packet: {err:?}");
+            return true;
+        }
+    };
+
+    let mut msgs = Vec::new();
+    unpack(packet, None, &mut msgs);
+
+    let now = SystemTime::now();
+
+    for (time, msg) in msgs {
+        match time.and_then(|time| time.duration_since(now).ok()) {
+            Some(delay) => {
+                log::debug!("[{name}] Scheduling message {:?} in {:?}", msg, delay);
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    let _ = tx.send(Event::Message(msg));
+                });
+            }
+            None => {
+                if tx.send(Event::Message(msg)).is_err() {
+                    log::info!("Failed to forward message, stopping task");
+                    return false;
+                }
+            }
         }
     }
 
     true
 }
 
-fn handle_send(name: &str, sock: &UdpSocket, msg: OscMessage) {
-    match encode(&OscPacket::Message(msg)) {
+/// Collect all messages of a packet together with the time they are scheduled for, `None` means
+/// immediately
+fn unpack(
+    packet: OscPacket,
+    time: Option<SystemTime>,
+    out: &mut Vec<(Option<SystemTime>, OscMessage)>,
+) {
+    match packet {
+        OscPacket::Message(msg) => out.push((time, msg)),
+        OscPacket::Bundle(bdl) => {
+            let time = if bdl.timetag == IMMEDIATELY {
+                time
+            } else {
+                Some(bdl.timetag.into())
+            };
+
+            for packet in bdl.content {
+                unpack(packet, time, out);
+            }
+        }
+    }
+}
+
+async fn handle_send(name: &str, sock: &UdpSocket, packet: OscPacket) -> io::Result<()> {
+    match encode(&packet) {
         Ok(out) => {
-            // TODO: log an error
-            sock.send(&out).unwrap();
+            sock.send(&out).await?;
         }
         Err(err) => {
             log::error!("[{}] Failed to encode packet: {:?}", name, err);
         }
     }
+
+    Ok(())
+}
+
+async fn handle_send_each(name: &str, sock: &UdpSocket, msgs: Vec<OscMessage>) -> io::Result<()> {
+    for msg in msgs {
+        log::debug!("Sending message {:?}", msg);
+        handle_send(name, sock, OscPacket::Message(msg)).await?;
+    }
+
+    Ok(())
+}
+
+/// Send `msgs` as few bundles as possible without exceeding `MAX_BUNDLE_SIZE`
+async fn handle_send_bundled(
+    name: &str,
+    sock: &UdpSocket,
+    msgs: Vec<OscMessage>,
+) -> io::Result<()> {
+    // "#bundle" and the time tag
+    const HEADER_SIZE: usize = 16;
+
+    let mut content = Vec::new();
+    let mut size = HEADER_SIZE;
+
+    for msg in msgs {
+        let packet = OscPacket::Message(msg);
+        // Every element is prefixed by its size
+        let len = match encode(&packet) {
+            Ok(out) => out.len() + 4,
+            Err(err) => {
+                log::error!("[{}] Failed to encode packet: {:?}", name, err);
+                continue;
+            }
+        };
+
+        if size + len > MAX_BUNDLE_SIZE && !content.is_empty() {
+            send_bundle(name, sock, std::mem::take(&mut content)).await?;
+            size = HEADER_SIZE;
+        }
+
+        content.push(packet);
+        size += len;
+    }
+
+    if !content.is_empty() {
+        send_bundle(name, sock, content).await?;
+    }
+
+    Ok(())
+}
+
+async fn send_bundle(name: &str, sock: &UdpSocket, mut content: Vec<OscPacket>) -> io::Result<()> {
+    let packet = if content.len() == 1 {
+        content.pop().unwrap()
+    } else {
+        OscPacket::Bundle(OscBundle {
+            timetag: IMMEDIATELY,
+            content,
+        })
+    };
+
+    handle_send(name, sock, packet).await
 }
 
 #[derive(Error, Debug)]
 pub enum OscDeviceError {
     #[error("Socket creation failed")]
     Socket(#[from] io::Error),
+    #[error("Device task has stopped")]
+    Closed,
 }
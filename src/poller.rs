@@ -0,0 +1,122 @@
+use crate::profile::{DeviceProfile, ParamId};
+use crate::rate::RateLimit;
+use rosc::OscMessage;
+use std::time::Duration;
+use tokio::time::Instant;
+
+// Parameters that changed within this time are polled before all others
+const RECENT: Duration = Duration::from_secs(2);
+
+/// Spreads the queries of a device over time, so that at most `rate` queries per second are
+/// sent. Queries that are due after a reset are sent first, then those of recently changed
+/// parameters and the others in the order they were last polled. Parameters that the device
+/// pushes are queried after a reset, and otherwise one of them per poll.
+#[derive(Default)]
+pub struct Poller {
+    entries: Vec<Entry>,
+    limit: Option<RateLimit>,
+}
+
+struct Entry {
+    msg: OscMessage,
+    // Parameters whose values are returned by the query
+    params: Vec<ParamId>,
+    // Whether the query is sent periodically, or only after a reset
+    periodic: bool,
+    // Whether the query has to be sent again since the last reset
+    due: bool,
+    last_sent: Option<Instant>,
+    last_change: Option<Instant>,
+}
+
+impl Poller {
+    /// Scheduler for the queries of `params`, all periodic ones are sent on every poll without a
+    /// rate. All queries are due at first.
+    pub fn new(profile: &dyn DeviceProfile, params: &[ParamId], rate: Option<f32>) -> Self {
+        let mut entries: Vec<Entry> = Vec::new();
+        let subscribed = profile.keep_alive().is_some();
+
+        for param in params {
+            let periodic = !subscribed || !profile.is_pushed(param);
+
+            for msg in profile.subscribe(std::slice::from_ref(param)) {
+                match entries.iter_mut().find(|e| e.msg.addr == msg.addr) {
+                    Some(entry) => {
+                        entry.params.push(param.clone());
+                        entry.periodic |= periodic;
+                    }
+                    None => entries.push(Entry {
+                        msg,
+                        params: vec![param.clone()],
+                        periodic,
+                        due: true,
+                        last_sent: None,
+                        last_change: None,
+                    }),
+                }
+            }
+        }
+
+        Poller {
+            entries,
+            limit: rate.map(RateLimit::new),
+        }
+    }
+
+    /// Poll the queries of `param` with priority for a while
+    pub fn changed(&mut self, param: &ParamId) {
+        let now = Instant::now();
+        for entry in &mut self.entries {
+            if entry.params.contains(param) {
+                entry.last_change = Some(now);
+            }
+        }
+    }
+
+    /// Make all queries due again, e.g. after the device has reconnected
+    pub fn reset(&mut self) {
+        for entry in &mut self.entries {
+            entry.due = true;
+        }
+    }
+
+    /// Queries that are due now within the budget
+    pub fn poll(&mut self) -> Vec<OscMessage> {
+        let now = Instant::now();
+
+        let mut order: Vec<usize> = (0..self.entries.len())
+            .filter(|&i| self.entries[i].periodic || self.entries[i].due)
+            .collect();
+
+        // A single query of a pushed parameter shows whether the device is still there even if
+        // nothing changes, and eventually catches up on notifications that got lost
+        if let Some(i) = (0..self.entries.len())
+            .filter(|&i| !self.entries[i].periodic && !self.entries[i].due)
+            .min_by_key(|&i| self.entries[i].last_sent)
+        {
+            order.push(i);
+        }
+
+        let count = match &mut self.limit {
+            Some(limit) => limit.take(order.len()),
+            None => order.len(),
+        };
+
+        order.sort_by_key(|&i| {
+            let entry = &self.entries[i];
+            let recent = entry.last_change.is_some_and(|t| now - t < RECENT);
+            (!entry.due, !recent, entry.last_sent)
+        });
+
+        order
+            .into_iter()
+            .take(count)
+            .map(|i| {
+                let entry = &mut self.entries[i];
+                entry.last_sent = Some(now);
+                entry.due = false;
+                entry.msg.clone()
+            })
+            .collect()
+    }
+}
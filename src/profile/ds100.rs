@@ -0,0 +1,194 @@
+use super::{DeviceProfile, ParamId, kind, query, set, value_arg};
+use crate::value::{Value, ValueKind};
+use rosc::OscMessage;
+
+const PARAMS: &[(&str, ValueKind)] = &[
+    ("position_x", ValueKind::Float),
+    ("position_y", ValueKind::Float),
+    ("position_z", ValueKind::Float),
+    ("spread", ValueKind::Float),
+    ("reverb_send_gain", ValueKind::Float),
+    ("reverb_gain", ValueKind::Float),
+    ("mute", ValueKind::Int),
+    ("name", ValueKind::String),
+    // Global En-Space room, only channel 1 exists
+    ("en_space_room", ValueKind::Int),
+];
+
+// Queries per second, d&b advise against flooding the OSC interface
+const POLL_RATE: f32 = 100.0;
+
+// Coordinate mapping used if the configuration doesn't choose one
+const DEFAULT_MAPPING_AREA: u8 = 1;
+
+/// d&b audiotechnik DS100, positions refer to one of its four coordinate mappings
+pub struct Ds100 {
+    area: u8,
+}
+
+impl Ds100 {
+    pub fn new(area: Option<u8>) -> Self {
+        Ds100 {
+            area: area.unwrap_or(DEFAULT_MAPPING_AREA),
+        }
+    }
+
+    fn area(&self, param: &ParamId) -> usize {
+        param.bank.unwrap_or(self.area.into())
+    }
+
+    fn address(&self, param: &ParamId) -> String {
+        let (n, area) = (param.index, self.area(param));
+        match param.name.as_str() {
+            "position_x" => format!(
+                "/dbaudio1/coordinatemapping/source_position_x/{}/{}",
+                area, n
+            ),
+            "position_y" => format!(
+                "/dbaudio1/coordinatemapping/source_position_y/{}/{}",
+                area, n
+            ),
+            "position_z" => format!(
+                "/dbaudio1/coordinatemapping/source_position_z/{}/{}",
+                area, n
+            ),
+            "spread" => format!("/dbaudio1/positioning/source_spread/{}", n),
+            "reverb_send_gain" => format!("/dbaudio1/matrixinput/reverbsendgain/{}", n),
+            "reverb_gain" => format!("/dbaudio1/reverbinputprocessing/gain/{}", n),
+            "mute" => format!("/dbaudio1/matrixinput/mute/{}", n),
+            "name" => format!("/dbaudio1/matrixinput/channelname/{}", n),
+            "en_space_room" => "/dbaudio1/matrixsettings/reverbroomid".to_owned(),
+            _ => unreachable!("Unknown DS100 parameter {}", param.name),
+        }
+    }
+}
+
+impl DeviceProfile for Ds100 {
+    fn default_port(&self) -> u16 {
+        50010
+    }
+
+    fn default_local_port(&self) -> u16 {
+        50011
+    }
+
+    fn default_poll_rate(&self) -> Option<f32> {
+        Some(POLL_RATE)
+    }
+
+    fn supports(&self, param: &str) -> bool {
+        kind(PARAMS, param).is_some()
+    }
+
+    fn bank(&self, param: &str, selected: Option<usize>) -> Option<usize> {
+        param
+            .starts_with("position_")
+            .then(|| selected.unwrap_or(self.area.into()))
+    }
+
+    fn subscribe(&self, params: &[ParamId]) -> Vec<OscMessage> {
+        let mut addrs: Vec<String> = Vec::new();
+
+        for param in params {
+            let n = param.index;
+            let addr = match param.name.as_str() {
+                // X and Y can only be queried together
+                "position_x" | "position_y" => format!(
+                    "/dbaudio1/coordinatemapping/source_position_xy/{}/{}",
+                    self.area(param),
+                    n
+                ),
+                "position_z" => format!(
+                    "/dbaudio1/coordinatemapping/source_position/{}/{}",
+                    self.area(param),
+                    n
+                ),
+                _ => self.address(param),
+            };
+
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+
+        addrs.into_iter().map(query).collect()
+    }
+
+    fn parse(&self, msg: &OscMessage) -> Vec<(ParamId, Value)> {
+        let parts: Vec<&str> = msg.addr.split('/').collect();
+
+        let (names, area, n): (&[&str], _, _) = match parts.as_slice() {
+            [
+                "",
+                "dbaudio1",
+                "coordinatemapping",
+                "source_position_xy",
+                area,
+                n,
+            ] => (&["position_x", "position_y"], Some(area), n),
+            [
+                "",
+                "dbaudio1",
+                "coordinatemapping",
+                "source_position",
+                area,
+                n,
+            ] => (&["position_x", "position_y", "position_z"], Some(area), n),
+            ["", "dbaudio1", "positioning", "source_spread", n] => (&["spread"], None, n),
+            ["", "dbaudio1", "matrixinput", "reverbsendgain", n] => {
+                (&["reverb_send_gain"], None, n)
+            }
+            ["", "dbaudio1", "reverbinputprocessing", "gain", n] => (&["reverb_gain"], None, n),
+            ["", "dbaudio1", "matrixinput", "mute", n] => (&["mute"], None, n),
+            ["", "dbaudio1", "matrixinput", "channelname", n] => (&["name"], None, n),
+            ["", "dbaudio1", "matrixsettings", "reverbroomid"] => (&["en_space_room"], None, &"1"),
+            _ => return Vec::new(),
+        };
+
+        let Ok(n) = n.parse() else {
+            return Vec::new();
+        };
+
+        names
+            .iter()
+            .enumerate()
+            .filter_map(|(i, name)| {
+                let param = match area {
+                    Some(area) => ParamId::banked(name, area.parse().ok()?, n),
+                    None => ParamId::new(name, n),
+                };
+                Some((param, value_arg(msg, i)?))
+            })
+            .collect()
+    }
+
+    fn render(&self, param: &ParamId, value: &Value) -> Option<OscMessage> {
+        set(self.address(param), value, kind(PARAMS, &param.name)?)
+    }
+
+    fn render_group(&self, values: &[(ParamId, Value)]) -> Option<Vec<OscMessage>> {
+        let names: Vec<&str> = values.iter().map(|(p, _)| p.name.as_str()).collect();
+
+        // Positions are set with a single message, so the object doesn't move diagonally
+        let addr = match names.as_slice() {
+            ["position_x", "position_y"] => "source_position_xy",
+            ["position_x", "position_y", "position_z"] => "source_position",
+            _ => return values.iter().map(|(p, v)| self.render(p, v)).collect(),
+        };
+
+        let args = values
+            .iter()
+            .map(|(_, v)| Some(v.convert(ValueKind::Float)?.into()))
+            .collect::<Option<_>>()?;
+
+        Some(vec![OscMessage {
+            addr: format!(
+                "/dbaudio1/coordinatemapping/{}/{}/{}",
+                addr,
+                self.area(&values[0].0),
+                values[0].0.index
+            ),
+            args,
+        }])
+    }
+}
@@ -0,0 +1,90 @@
+use super::{DeviceProfile, ParamId, query, set, value_arg};
+use crate::config::{ConfigError, DeviceConfig, ParamConfig};
+use crate::value::Value;
+use rosc::OscMessage;
+use std::collections::BTreeMap;
+
+/// Device whose parameters are declared in the configuration file, the address templates use
+/// `{n}` as the placeholder for the channel number
+pub struct Generic {
+    port: u16,
+    params: BTreeMap<String, ParamConfig>,
+}
+
+impl Generic {
+    pub fn new(name: &str, config: &DeviceConfig) -> Result<Self, ConfigError> {
+        let port = config
+            .port
+            .ok_or_else(|| ConfigError::MissingPort(name.to_owned()))?;
+
+        for (param, param_config) in &config.params {
+            for template in [&param_config.address, param_config.listen()] {
+                if !template.contains("{n}") {
+                    return Err(ConfigError::InvalidTemplate(name.to_owned(), param.clone()));
+                }
+            }
+        }
+
+        Ok(Generic {
+            port,
+            params: config.params.clone(),
+        })
+    }
+}
+
+impl DeviceProfile for Generic {
+    fn default_port(&self) -> u16 {
+        self.port
+    }
+
+    fn supports(&self, param: &str) -> bool {
+        self.params.contains_key(param)
+    }
+
+    fn subscribe(&self, params: &[ParamId]) -> Vec<OscMessage> {
+        let mut addrs: Vec<String> = Vec::new();
+
+        for param in params {
+            let addr = render(self.params[&param.name].listen(), param.index);
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+
+        addrs.into_iter().map(query).collect()
+    }
+
+    fn parse(&self, msg: &OscMessage) -> Vec<(ParamId, Value)> {
+        self.params
+            .iter()
+            .filter_map(|(name, config)| {
+                let n = match_template(config.listen(), &msg.addr)?;
+                Some((ParamId::new(name, n), value_arg(msg, config.arg)?))
+            })
+            .collect()
+    }
+
+    fn render(&self, param: &ParamId, value: &Value) -> Option<OscMessage> {
+        let config = &self.params[&param.name];
+        let addr = render(&config.address, param.index);
+
+        match config.kind {
+            Some(kind) => set(addr, value, kind),
+            // Without a declared type, values are sent as received
+            None => Some(OscMessage {
+                addr,
+                args: vec![value.clone().into()],
+            }),
+        }
+    }
+}
+
+fn render(template: &str, n: usize) -> String {
+    template.replace("{n}", &n.to_string())
+}
+
+fn match_template(template: &str, addr: &str) -> Option<usize> {
+    let (prefix, suffix) = template.split_once("{n}")?;
+    let n = addr.strip_prefix(prefix)?.strip_suffix(suffix)?;
+    n.parse().ok()
+}
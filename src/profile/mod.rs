@@ -0,0 +1,131 @@
+mod ds100;
+mod generic;
+mod wing;
+mod x32;
+
+pub use ds100::Ds100;
+pub use generic::Generic;
+pub use wing::Wing;
+pub use x32::X32;
+
+use crate::config::{ConfigError, DeviceConfig, ProfileKind};
+use crate::value::{Value, ValueKind};
+use rosc::OscMessage;
+use std::time::Duration;
+
+/// Identifies a single value on a device, e.g. the pan of the first send of WING channel 3
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ParamId {
+    pub name: String,
+    /// Block of the channel the parameter belongs to if there are several, e.g. the send of a
+    /// WING channel or the coordinate mapping of a DS100 position
+    pub bank: Option<usize>,
+    pub index: usize,
+}
+
+/// Describes how to talk to a specific kind of device
+pub trait DeviceProfile {
+    /// Port the device receives OSC messages on
+    fn default_port(&self) -> u16;
+
+    /// Local port the device expects to send its replies to, 0 if it replies to the sender
+    fn default_local_port(&self) -> u16 {
+        0
+    }
+
+    /// Queries per second the device should be sent at most, `None` if it can take them all at
+    /// once
+    fn default_poll_rate(&self) -> Option<f32> {
+        None
+    }
+
+    /// Whether the device has a parameter of the given name
+    fn supports(&self, param: &str) -> bool;
+
+    /// Bank of a parameter that a member refers to, `selected` if given or else the default of
+    /// the device, `None` for parameters that don't come in banks
+    fn bank(&self, _param: &str, _selected: Option<usize>) -> Option<usize> {
+        None
+    }
+
+    /// Messages that query the current values of `params`, sent periodically for parameters that
+    /// the device doesn't push
+    fn subscribe(&self, params: &[ParamId]) -> Vec<OscMessage>;
+
+    /// Message that registers for change notifications and the interval it has to be renewed
+    /// in, `None` if all parameters have to be polled
+    fn keep_alive(&self) -> Option<(OscMessage, Duration)> {
+        None
+    }
+
+    /// Whether changes of `param` are notified while registered
+    fn is_pushed(&self, _param: &ParamId) -> bool {
+        false
+    }
+
+    /// All parameter values contained in a received message
+    fn parse(&self, msg: &OscMessage) -> Vec<(ParamId, Value)>;
+
+    /// Message that sets `param` to `value`, `None` if the value can't be converted to the type
+    /// of the parameter
+    fn render(&self, param: &ParamId, value: &Value) -> Option<OscMessage>;
+
+    /// Messages that set several parameters that are synchronised together, devices that can set
+    /// them atomically combine them into one message
+    fn render_group(&self, values: &[(ParamId, Value)]) -> Option<Vec<OscMessage>> {
+        values.iter().map(|(p, v)| self.render(p, v)).collect()
+    }
+}
+
+impl ParamId {
+    pub fn new(name: &str, index: usize) -> Self {
+        ParamId {
+            name: name.to_owned(),
+            bank: None,
+            index,
+        }
+    }
+
+    pub fn banked(name: &str, bank: usize, index: usize) -> Self {
+        ParamId {
+            bank: Some(bank),
+            ..ParamId::new(name, index)
+        }
+    }
+}
+
+pub fn create(name: &str, config: &DeviceConfig) -> Result<Box<dyn DeviceProfile>, ConfigError> {
+    Ok(match config.profile {
+        Some(ProfileKind::Wing) => Box::new(Wing::new(config.send)),
+        Some(ProfileKind::Ds100) => Box::new(Ds100::new(config.mapping_area)),
+        Some(ProfileKind::X32) => Box::new(X32::new(config.send)),
+        Some(ProfileKind::XAir) => Box::new(X32::xair(config.send)),
+        None => Box::new(Generic::new(name, config)?),
+    })
+}
+
+/// Type of the parameter `name` in a table of the parameters of a profile
+fn kind(params: &[(&str, ValueKind)], name: &str) -> Option<ValueKind> {
+    params
+        .iter()
+        .find(|(param, _)| *param == name)
+        .map(|(_, kind)| *kind)
+}
+
+fn query(addr: String) -> OscMessage {
+    OscMessage {
+        addr,
+        args: Vec::new(),
+    }
+}
+
+fn set(addr: String, value: &Value, kind: ValueKind) -> Option<OscMessage> {
+    Some(OscMessage {
+        addr,
+        args: vec![value.convert(kind)?.into()],
+    })
+}
+
+fn value_arg(msg: &OscMessage, index: usize) -> Option<Value> {
+    Value::from_osc(msg.args.get(index)?)
+}
@@ -0,0 +1,109 @@
+use super::{DeviceProfile, ParamId, kind, query, set, value_arg};
+use crate::value::{Value, ValueKind};
+use rosc::OscMessage;
+use std::time::Duration;
+
+const PARAMS: &[(&str, ValueKind)] = &[
+    ("send_pan", ValueKind::Float),
+    ("send_wid", ValueKind::Float),
+    ("send_lvl", ValueKind::Float),
+    ("bus_fdr", ValueKind::Float),
+    ("mute", ValueKind::Int),
+    ("name", ValueKind::String),
+    ("col", ValueKind::Int),
+];
+
+// Address that registers for change notifications, they are sent for 10 s after the last
+// registration
+const SUBSCRIBE: &str = "/*S";
+const KEEP_ALIVE: Duration = Duration::from_secs(5);
+
+// Send that channel send parameters refer to if the configuration doesn't choose one
+const DEFAULT_SEND: u8 = 1;
+
+/// Behringer WING, channel send parameters refer to one of the sends of the channel
+pub struct Wing {
+    send: u8,
+}
+
+impl Wing {
+    pub fn new(send: Option<u8>) -> Self {
+        Wing {
+            send: send.unwrap_or(DEFAULT_SEND),
+        }
+    }
+}
+
+impl DeviceProfile for Wing {
+    fn default_port(&self) -> u16 {
+        2223
+    }
+
+    fn supports(&self, param: &str) -> bool {
+        kind(PARAMS, param).is_some()
+    }
+
+    fn bank(&self, param: &str, selected: Option<usize>) -> Option<usize> {
+        param
+            .starts_with("send_")
+            .then(|| selected.unwrap_or(self.send.into()))
+    }
+
+    fn subscribe(&self, params: &[ParamId]) -> Vec<OscMessage> {
+        params.iter().map(|p| query(address(p))).collect()
+    }
+
+    fn keep_alive(&self) -> Option<(OscMessage, Duration)> {
+        Some((query(SUBSCRIBE.to_owned()), KEEP_ALIVE))
+    }
+
+    fn is_pushed(&self, param: &ParamId) -> bool {
+        kind(PARAMS, &param.name).is_some()
+    }
+
+    fn parse(&self, msg: &OscMessage) -> Vec<(ParamId, Value)> {
+        // The WING answers with the value as string, the normalised value and the actual value,
+        // strings only come with the first
+        let last = msg.args.len().saturating_sub(1);
+        match (parse_address(&msg.addr), value_arg(msg, last)) {
+            (Some(param), Some(value)) => vec![(param, value)],
+            _ => Vec::new(),
+        }
+    }
+
+    fn render(&self, param: &ParamId, value: &Value) -> Option<OscMessage> {
+        set(address(param), value, kind(PARAMS, &param.name)?)
+    }
+}
+
+fn address(param: &ParamId) -> String {
+    let (n, send) = (param.index, param.bank.unwrap_or(DEFAULT_SEND.into()));
+    match param.name.as_str() {
+        "send_pan" => format!("/ch/{}/send/{}/pan", n, send),
+        "send_wid" => format!("/ch/{}/send/{}/wid", n, send),
+        "send_lvl" => format!("/ch/{}/send/{}/lvl", n, send),
+        "bus_fdr" => format!("/bus/{}/fdr", n),
+        "mute" => format!("/ch/{}/mute", n),
+        "name" => format!("/ch/{}/name", n),
+        "col" => format!("/ch/{}/col", n),
+        _ => unreachable!("Unknown WING parameter {}", param.name),
+    }
+}
+
+fn parse_address(addr: &str) -> Option<ParamId> {
+    let parts: Vec<&str> = addr.split('/').collect();
+
+    let (name, n) = match parts.as_slice() {
+        ["", "ch", n, "send", send, param @ ("pan" | "wid" | "lvl")] => {
+            let name = format!("send_{}", param);
+            return Some(ParamId::banked(&name, send.parse().ok()?, n.parse().ok()?));
+        }
+        ["", "bus", n, "fdr"] => ("bus_fdr", n),
+        ["", "ch", n, "mute"] => ("mute", n),
+        ["", "ch", n, "name"] => ("name", n),
+        ["", "ch", n, "col"] => ("col", n),
+        _ => return None,
+    };
+
+    Some(ParamId::new(name, n.parse().ok()?))
+}
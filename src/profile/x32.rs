@@ -0,0 +1,336 @@
+use super::{DeviceProfile, ParamId, kind, query, set, value_arg};
+use crate::value::{Value, ValueKind};
+use rosc::OscMessage;
+use std::time::Duration;
+
+const PARAMS: &[(&str, ValueKind)] = &[
+    ("send_pan", ValueKind::Float),
+    ("send_lvl", ValueKind::Float),
+    ("pan", ValueKind::Float),
+    ("fdr", ValueKind::Float),
+    ("bus_fdr", ValueKind::Float),
+    ("mute", ValueKind::Int),
+    ("name", ValueKind::String),
+    ("col", ValueKind::Int),
+];
+
+// Makes the console report all changes made on it or by other clients for the next 10 s
+const SUBSCRIBE: &str = "/xremote";
+const KEEP_ALIVE: Duration = Duration::from_secs(5);
+
+// Mix send that channel send parameters refer to if the configuration doesn't choose one
+const DEFAULT_SEND: u8 = 1;
+
+// Level reported for a fader at the bottom, the same as the lowest WING level
+const OFF_DB: f64 = -144.0;
+
+/// Behringer X32 and Midas M32, or Behringer X-Air and Midas MR mixers. Pans are converted to
+/// -100..100 and fader positions to dB like the WING uses them, so all consoles work with the
+/// same mappings.
+pub struct X32 {
+    send: u8,
+    family: Family,
+}
+
+/// The families share the address layout, but differ in the port and the numbering of buses
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Family {
+    X32,
+    XAir,
+}
+
+impl X32 {
+    pub fn new(send: Option<u8>) -> Self {
+        X32 {
+            send: send.unwrap_or(DEFAULT_SEND),
+            family: Family::X32,
+        }
+    }
+
+    pub fn xair(send: Option<u8>) -> Self {
+        X32 {
+            family: Family::XAir,
+            ..X32::new(send)
+        }
+    }
+
+    fn address(&self, param: &ParamId) -> String {
+        match (param.name.as_str(), self.family) {
+            // X-Air buses are not zero-padded
+            ("bus_fdr", Family::XAir) => format!("/bus/{}/mix/fader", param.index),
+            _ => address(param),
+        }
+    }
+}
+
+impl DeviceProfile for X32 {
+    fn default_port(&self) -> u16 {
+        match self.family {
+            Family::X32 => 10023,
+            Family::XAir => 10024,
+        }
+    }
+
+    fn supports(&self, param: &str) -> bool {
+        kind(PARAMS, param).is_some()
+    }
+
+    fn bank(&self, param: &str, selected: Option<usize>) -> Option<usize> {
+        param
+            .starts_with("send_")
+            .then(|| selected.unwrap_or(self.send.into()))
+    }
+
+    fn subscribe(&self, params: &[ParamId]) -> Vec<OscMessage> {
+        params.iter().map(|p| query(self.address(p))).collect()
+    }
+
+    fn keep_alive(&self) -> Option<(OscMessage, Duration)> {
+        Some((query(SUBSCRIBE.to_owned()), KEEP_ALIVE))
+    }
+
+    fn is_pushed(&self, param: &ParamId) -> bool {
+        kind(PARAMS, &param.name).is_some()
+    }
+
+    fn parse(&self, msg: &OscMessage) -> Vec<(ParamId, Value)> {
+        let (Some(param), Some(value)) = (parse_address(&msg.addr), value_arg(msg, 0)) else {
+            return Vec::new();
+        };
+
+        let value = match param.name.as_str() {
+            "send_pan" | "pan" => value.map_number(|x| x * 200.0 - 100.0),
+            "send_lvl" | "fdr" | "bus_fdr" => value.map_number(fader_to_db),
+            // The console reports whether the channel is on
+            "mute" => value.map_number(|on| 1.0 - on),
+            _ => value,
+        };
+
+        vec![(param, value)]
+    }
+
+    fn render(&self, param: &ParamId, value: &Value) -> Option<OscMessage> {
+        let kind = kind(PARAMS, &param.name)?;
+        let value = value.convert(kind)?;
+
+        let value = match param.name.as_str() {
+            "send_pan" | "pan" => value.map_number(|x| ((x + 100.0) / 200.0).clamp(0.0, 1.0)),
+            "send_lvl" | "fdr" | "bus_fdr" => value.map_number(db_to_fader),
+            "mute" => value.map_number(|muted| 1.0 - muted),
+            _ => value,
+        };
+
+        set(self.address(param), &value, kind)
+    }
+}
+
+fn address(param: &ParamId) -> String {
+    let (n, send) = (param.index, param.bank.unwrap_or(DEFAULT_SEND.into()));
+    match param.name.as_str() {
+        "send_pan" => format!("/ch/{:02}/mix/{:02}/pan", n, send),
+        "send_lvl" => format!("/ch/{:02}/mix/{:02}/level", n, send),
+        "pan" => format!("/ch/{:02}/mix/pan", n),
+        "fdr" => format!("/ch/{:02}/mix/fader", n),
+        "bus_fdr" => format!("/bus/{:02}/mix/fader", n),
+        "mute" => format!("/ch/{:02}/mix/on", n),
+        "name" => format!("/ch/{:02}/config/name", n),
+        "col" => format!("/ch/{:02}/config/color", n),
+        _ => unreachable!("Unknown X32 parameter {}", param.name),
+    }
+}
+
+fn parse_address(addr: &str) -> Option<ParamId> {
+    let parts: Vec<&str> = addr.split('/').collect();
+
+    let (name, n) = match parts.as_slice() {
+        ["", "ch", n, "mix", send, param @ ("pan" | "level")] => {
+            let name = if *param == "pan" {
+                "send_pan"
+            } else {
+                "send_lvl"
+            };
+            return Some(ParamId::banked(name, send.parse().ok()?, n.parse().ok()?));
+        }
+        ["", "ch", n, "mix", "pan"] => ("pan", n),
+        ["", "ch", n, "mix", "fader"] => ("fdr", n),
+        ["", "bus", n, "mix", "fader"] => ("bus_fdr", n),
+        ["", "ch", n, "mix", "on"] => ("mute", n),
+        ["", "ch", n, "config", "name"] => ("name", n),
+        ["", "ch", n, "config", "color"] => ("col", n),
+        _ => return None,
+    };
+
+    Some(ParamId::new(name, n.parse().ok()?))
+}
+
+/// Fader position (0..1) to dB, the faders have four linear segments from -oo to +10 dB
+fn fader_to_db(f: f64) -> f64 {
+    if f >= 0.5 {
+        f * 40.0 - 30.0
+    } else if f >= 0.25 {
+        f * 80.0 - 50.0
+    } else if f >= 0.0625 {
+        f * 160.0 - 70.0
+    } else if f > 0.0 {
+        f * 480.0 - 90.0
+    } else {
+        OFF_DB
+    }
+}
+
+fn db_to_fader(db: f64) -> f64 {
+    let f = if db >= -10.0 {
+        (db + 30.0) / 40.0
+    } else if db >= -30.0 {
+        (db + 50.0) / 80.0
+    } else if db >= -60.0 {
+        (db + 70.0) / 160.0
+    } else {
+        (db + 90.0) / 480.0
+    };
+    f.clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rosc::OscType;
+
+    fn msg(addr: &str, arg: OscType) -> OscMessage {
+        OscMessage {
+            addr: addr.to_owned(),
+            args: vec![arg],
+        }
+    }
+
+    fn parse_one(profile: &X32, msg: &OscMessage) -> (ParamId, f64) {
+        let mut values = profile.parse(msg);
+        assert_eq!(values.len(), 1, "{:?}", msg);
+        let (param, value) = values.remove(0);
+        (param, value.as_f64().unwrap())
+    }
+
+    fn render_one(profile: &X32, param: &ParamId, value: Value) -> (String, OscType) {
+        let mut msg = profile.render(param, &value).unwrap();
+        (msg.addr, msg.args.remove(0))
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn fader_breakpoints() {
+        for (fader, db) in [
+            (0.0625, -60.0),
+            (0.25, -30.0),
+            (0.5, -10.0),
+            (0.75, 0.0),
+            (1.0, 10.0),
+        ] {
+            assert_eq!(fader_to_db(fader), db);
+            assert_eq!(db_to_fader(db), fader);
+        }
+
+        assert_eq!(fader_to_db(0.0), OFF_DB);
+        assert_eq!(db_to_fader(OFF_DB), 0.0);
+        assert_eq!(db_to_fader(20.0), 1.0);
+    }
+
+    #[test]
+    fn fader_round_trip() {
+        for i in 1..=1024 {
+            let fader = i as f64 / 1024.0;
+            assert_close(db_to_fader(fader_to_db(fader)), fader);
+        }
+        for db in [
+            -89.0, -75.0, -60.0, -45.5, -30.0, -20.0, -10.0, -3.0, 0.0, 6.0, 10.0,
+        ] {
+            assert_close(fader_to_db(db_to_fader(db)), db);
+        }
+    }
+
+    #[test]
+    fn parse_converts_values() {
+        let x32 = X32::new(None);
+
+        let (param, pan) = parse_one(&x32, &msg("/ch/03/mix/02/pan", OscType::Float(0.75)));
+        assert_eq!(param, ParamId::banked("send_pan", 2, 3));
+        assert_close(pan, 50.0);
+
+        let (param, db) = parse_one(&x32, &msg("/ch/12/mix/fader", OscType::Float(0.5)));
+        assert_eq!(param, ParamId::new("fdr", 12));
+        assert_close(db, -10.0);
+
+        let (param, muted) = parse_one(&x32, &msg("/ch/05/mix/on", OscType::Int(1)));
+        assert_eq!(param, ParamId::new("mute", 5));
+        assert_eq!(muted, 0.0);
+
+        assert!(
+            x32.parse(&msg("/ch/01/eq/1/f", OscType::Float(0.5)))
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn render_uses_zero_padded_addresses() {
+        let x32 = X32::new(None);
+
+        let (addr, arg) = render_one(
+            &x32,
+            &ParamId::banked("send_lvl", 2, 3),
+            Value::Float(-10.0),
+        );
+        assert_eq!(addr, "/ch/03/mix/02/level");
+        assert_eq!(arg, OscType::Float(0.5));
+
+        let (addr, arg) = render_one(
+            &x32,
+            &ParamId::banked("send_pan", 12, 7),
+            Value::Float(-100.0),
+        );
+        assert_eq!(addr, "/ch/07/mix/12/pan");
+        assert_eq!(arg, OscType::Float(0.0));
+
+        let (addr, arg) = render_one(&x32, &ParamId::new("mute", 1), Value::Int(1));
+        assert_eq!(addr, "/ch/01/mix/on");
+        assert_eq!(arg, OscType::Int(0));
+
+        let (addr, _) = render_one(&x32, &ParamId::new("bus_fdr", 2), Value::Float(0.0));
+        assert_eq!(addr, "/bus/02/mix/fader");
+    }
+
+    #[test]
+    fn xair_buses_are_not_padded() {
+        let xair = X32::xair(None);
+
+        let (addr, arg) = render_one(&xair, &ParamId::new("bus_fdr", 2), Value::Float(0.0));
+        assert_eq!(addr, "/bus/2/mix/fader");
+        assert_eq!(arg, OscType::Float(0.75));
+
+        let (param, db) = parse_one(&xair, &msg("/bus/2/mix/fader", OscType::Float(0.75)));
+        assert_eq!(param, ParamId::new("bus_fdr", 2));
+        assert_close(db, 0.0);
+
+        let (addr, _) = render_one(&xair, &ParamId::new("fdr", 4), Value::Float(0.0));
+        assert_eq!(addr, "/ch/04/mix/fader");
+    }
+
+    #[test]
+    fn rendered_values_parse_back() {
+        let x32 = X32::new(None);
+
+        for (param, value) in [
+            (ParamId::banked("send_pan", 1, 1), 37.5),
+            (ParamId::new("pan", 32), -12.0),
+            (ParamId::new("fdr", 16), -42.0),
+            (ParamId::new("bus_fdr", 8), 4.5),
+            (ParamId::new("mute", 2), 1.0),
+        ] {
+            let msg = x32.render(&param, &Value::Double(value)).unwrap();
+            let (parsed, parsed_value) = parse_one(&x32, &msg);
+            assert_eq!(parsed, param);
+            assert_close(parsed_value, value);
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use std::time::Duration;
+use tokio::time::Instant;
+
+// Longest time an unused budget is saved up for, limits the size of bursts
+const MAX_BURST: Duration = Duration::from_millis(200);
+
+/// Budget of `rate` actions per second that is refilled continuously
+#[derive(Clone, Debug)]
+pub struct RateLimit {
+    rate: f32,
+    budget: f32,
+    last_update: Instant,
+}
+
+impl RateLimit {
+    /// Limit that starts with a full burst
+    pub fn new(rate: f32) -> Self {
+        RateLimit {
+            rate,
+            budget: max_budget(rate),
+            last_update: Instant::now(),
+        }
+    }
+
+    /// Use up to `count` actions of the budget, returns how many are allowed now
+    pub fn take(&mut self, count: usize) -> usize {
+        self.refill();
+        let count = (self.budget as usize).min(count);
+        self.budget -= count as f32;
+        count
+    }
+
+    /// Time at which the next action is allowed
+    pub fn next_available(&mut self) -> Instant {
+        self.refill();
+        let missing = (1.0 - self.budget).max(0.0);
+        self.last_update + Duration::from_secs_f32(missing / self.rate)
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = (now - self.last_update).as_secs_f32();
+        self.budget = (self.budget + self.rate * elapsed).min(max_budget(self.rate));
+        self.last_update = now;
+    }
+}
+
+fn max_budget(rate: f32) -> f32 {
+    (rate * MAX_BURST.as_secs_f32()).max(1.0)
+}
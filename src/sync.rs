@@ -1,178 +1,443 @@
+use std::fmt::Display;
+use std::rc::Rc;
 use std::time::{Duration, Instant};
 
-type T = f32;
+// Defaults of the settings, mappings can override them
+
+// Differences up to this value count as equal
 const EPS: f32 = 0.01;
 
 // If the difference is larger than this value, we force a synchronisation
 const FORCE_SYNC_EPS: f32 = 1.0;
 
-// How long does the current side count as master?
+// How long does the current master keep its role?
 const MASTER_DURATION: Duration = Duration::from_millis(250);
 
-// Who wins if there is a discrepancy (incomplete update)?
-const DEFAULT_MASTER: Side = Side::Right;
-
-#[derive(Clone)]
-pub struct Sync {
-    name: String,
-    left: SyncItem,
-    right: SyncItem,
-    last_flush: Option<Instant>,
-    current_master: Option<Side>,
+// How long are sent values expected to come back from the device?
+const ECHO_TIMEOUT: Duration = Duration::from_secs(1);
 
-    // How to transform "left" to "right"
-    l2r: fn(T) -> T,
-    r2l: fn(T) -> T,
+/// Value that can be synchronised between devices
+pub trait SyncValue: Clone + Default + Display {
+    /// Whether the values are equal, continuous values may differ by up to `eps`
+    fn matches(&self, other: &Self, eps: f32) -> bool;
 }
 
-#[derive(Copy, Clone, Debug)]
-pub enum Side {
-    Left,
-    Right,
+/// How conflicting changes of the members are resolved
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    /// The most recent change wins, members that drift apart are forced to the default master
+    LastWriter,
+    /// Like `LastWriter`, but members that drift apart are left alone
+    NeverForce,
+    /// Only changes of the given member are forwarded, all others are reset to its value
+    Authoritative(usize),
+    /// Members with a lower index take over at any time, changes of the others are reverted while
+    /// the current master is locked
+    Priority,
 }
 
-impl Side {
-    fn flip(&self) -> Self {
-        match self {
-            Left => Right,
-            Right => Left,
-        }
-    }
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub strategy: Strategy,
+    /// How long the current master keeps its role after its last change
+    pub lockout: Duration,
+    /// Differences up to this value count as equal
+    pub eps: f32,
+    /// Members that differ by more than this are forced to the default master
+    pub force_eps: f32,
+    /// Received values that differ by up to this value from a value the bridge has just sent
+    /// count as its echo
+    pub echo_eps: f32,
 }
 
-pub use Side::*;
+#[derive(Clone)]
+pub struct Sync<T: SyncValue> {
+    name: String,
+    settings: Settings,
+    members: Vec<SyncItem<T>>,
+    last_flush: Option<Instant>,
+    current_master: Option<usize>,
+
+    // Who wins if there is a discrepancy (incomplete update)?
+    default_master: Option<usize>,
+}
 
-#[derive(Clone, Debug)]
-struct SyncItem {
+#[derive(Clone)]
+struct SyncItem<T> {
     last_update: Option<Instant>,
     value: T,
-}
+    // Whether the device is currently answering, values of offline devices might be stale
+    online: bool,
+    // Whether the device has reported a value since it was last seen going online or offline
+    known: bool,
+    // Whether the first value after a reconnect has arrived and has to be reconciled
+    rejoined: bool,
+    // Values that have been sent to the device and when, their echoes are not changes
+    in_flight: Vec<(T, Instant)>,
 
-impl Sync {
-    pub fn new(name: String) -> Self {
-        Self::with_transform(name, |x| x, |x| x)
-    }
+    // How to transform the value of this member to the canonical value and back
+    to_canonical: Rc<dyn Fn(T) -> T>,
+    from_canonical: Rc<dyn Fn(T) -> T>,
+}
 
-    pub fn with_transform(name: String, l2r: fn(T) -> T, r2l: fn(T) -> T) -> Self {
+impl<T: SyncValue> Sync<T> {
+    pub fn new(name: String, settings: Settings) -> Self {
         Sync {
             name,
-            left: SyncItem::new(),
-            right: SyncItem::new(),
+            settings,
+            members: Vec::new(),
             current_master: None,
+            default_master: None,
             last_flush: None,
-            l2r,
-            r2l,
         }
     }
 
-    pub fn update(&mut self, side: Side, value: T) -> bool {
-        match side {
-            Left => self.left.update(value),
-            Right => self.right.update(value),
+    /// Add a participant whose values are converted to and from the canonical value with the
+    /// given functions, returns its index
+    pub fn add_member(
+        &mut self,
+        to_canonical: impl Fn(T) -> T + 'static,
+        from_canonical: impl Fn(T) -> T + 'static,
+    ) -> usize {
+        self.members.push(SyncItem::new(
+            Rc::new(to_canonical),
+            Rc::new(from_canonical),
+        ));
+        self.members.len() - 1
+    }
+
+    pub fn set_default_master(&mut self, member: Option<usize>) {
+        self.default_master = member;
+    }
+
+    /// Values of a member that goes offline or comes back are unknown until it reports them again
+    pub fn set_online(&mut self, member: usize, online: bool) {
+        let item = &mut self.members[member];
+        if item.online != online {
+            item.online = online;
+            item.known = false;
+            item.in_flight.clear();
+        }
+    }
+
+    pub fn update(&mut self, member: usize, value: T) -> bool {
+        let item = &mut self.members[member];
+
+        // Once the synchronisation is running, the first value after a reconnect is the state the
+        // device kept while it was away and no change, it is reconciled with the next flush
+        if !item.known && self.last_flush.is_some() {
+            item.known = true;
+            item.rejoined = true;
+            item.value = value;
+            return false;
         }
+
+        item.update(value, &self.settings)
     }
 
     // pub fn last_update(&self) -> Option<Instant> {
-    //     self.left.last_update.max(self.right.last_update)
+    //     self.members.iter().map(|m| m.last_update).max().flatten()
     // }
 
-    pub fn left_value(&self) -> T {
-        self.left.value
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self, member: usize) -> &T {
+        &self.members[member].value
+    }
+
+    /// Member whose changes are forwarded at the moment, the authority if there is one
+    pub fn current_master(&self) -> Option<usize> {
+        self.current_master.or(self.authority())
+    }
+
+    /// Whether all members have reported their value
+    pub fn is_complete(&self) -> bool {
+        self.members.iter().all(|m| m.known)
+    }
+
+    pub fn is_known(&self, member: usize) -> bool {
+        self.members[member].known
+    }
+
+    /// Take the current values as the starting point for the synchronisation. Returns the updates
+    /// that are needed to make the default master win if the members differ, members that
+    /// haven't reported their values get those of the master. Nothing is changed if the master's
+    /// own values are missing.
+    pub fn reconcile(&mut self) -> Vec<(T, usize)> {
+        self.last_flush = Some(Instant::now());
+        self.current_master = None;
+
+        let Some(master) = self
+            .authority()
+            .or(self.default_master)
+            .filter(|&m| self.members[m].known)
+        else {
+            return Vec::new();
+        };
+
+        let updates = self.get_flush_result(master, self.settings.eps);
+        self.expect(&updates);
+        updates
+    }
+
+    /// Updates that make all members match `master` again after they have drifted apart by less
+    /// than the force threshold. Nothing is changed while a member is being moved or not
+    /// answering, or if the strategy never forces values. With `dry_run`, the updates are only
+    /// reported.
+    pub fn resync(&mut self, master: Option<usize>, dry_run: bool) -> Vec<(T, usize)> {
+        let Some(master) = self.authority().or(master) else {
+            return Vec::new();
+        };
+
+        if self.settings.strategy == Strategy::NeverForce
+            || self.current_master.is_some()
+            || !self.members.iter().all(|m| m.online && m.known)
+        {
+            return Vec::new();
+        }
+
+        // Rounding by the devices is no drift
+        let eps = self.settings.eps.max(self.settings.echo_eps);
+        let updates = self.get_flush_result(master, eps);
+        if !dry_run {
+            self.expect(&updates);
+        }
+        updates
     }
 
-    pub fn right_value(&self) -> T {
-        self.right.value
+    /// Updates that have to be sent to the other members, `(value, member)`
+    pub fn flush(&mut self) -> Vec<(T, usize)> {
+        let mut updates = self.rejoin();
+        self.expect(&updates);
+
+        let elected = self.elect();
+        self.expect(&elected);
+        updates.extend(elected);
+        updates
     }
 
-    pub fn current_master(&self) -> Option<Side> {
-        self.current_master
+    /// Updates that bring members which have reconnected up to date. The startup master wins
+    /// unless it is the member itself, whose values are stale, then another member that stayed
+    /// online does. Without a startup master nothing is changed.
+    fn rejoin(&mut self) -> Vec<(T, usize)> {
+        let mut updates = Vec::new();
+
+        for member in 0..self.members.len() {
+            if !std::mem::take(&mut self.members[member].rejoined) {
+                continue;
+            }
+
+            let master = match self.authority() {
+                Some(authority) => authority,
+                None => {
+                    let Some(default_master) = self.default_master else {
+                        continue;
+                    };
+                    let usable =
+                        |&m: &usize| m != member && self.members[m].online && self.members[m].known;
+                    match Some(default_master)
+                        .filter(usable)
+                        .or_else(|| (0..self.members.len()).find(usable))
+                    {
+                        Some(master) => master,
+                        None => continue,
+                    }
+                }
+            };
+
+            for (value, m) in self.get_flush_result(master, self.settings.eps) {
+                // Members only follow the authority when it is the one that reconnected
+                if m != member && master != member {
+                    continue;
+                }
+
+                log::info!(
+                    "{}: member {} reconnected, changing member {} from {} to {}",
+                    self.name,
+                    member,
+                    m,
+                    self.members[m].value,
+                    value
+                );
+                updates.push((value, m));
+            }
+        }
+
+        updates
     }
 
-    pub fn flush(&mut self) -> Option<(T, Side)> {
+    fn elect(&mut self) -> Vec<(T, usize)> {
         let now = Instant::now();
-        let threshold = now - MASTER_DURATION;
+        let threshold = now - self.settings.lockout;
+        let eps = self.settings.eps;
 
         let prev_flush = self.last_flush;
         self.last_flush = Some(now);
 
-        match self.current_master {
-            // TODO: Handle unsynchronised case! (Every minute from Left -> RIght)
-            Some(master) => {
-                let item = self.get_item(master);
+        // Members that have changed since the last flush, ordered by priority
+        let changed: Vec<usize> = (0..self.members.len())
+            .filter(|&i| self.members[i].last_update > prev_flush)
+            .collect();
 
-                if item.last_update < Some(threshold) {
-                    // log::info!("Last update: {:?} < threshold {:?}, resetting", item.last_update, threshold);
-                    self.current_master = None;
-                    return None;
-                }
+        if let Some(authority) = self.authority() {
+            // Changes of all other members are reverted. The authority never becomes the current
+            // master, which would keep the resync and the force sync away.
+            if !changed.is_empty() {
+                return self.get_flush_result(authority, eps);
+            }
+        } else if let Some(master) = self.current_master {
+            let priority = self.settings.strategy == Strategy::Priority;
 
-                if item.last_update > prev_flush {
-                    // log::info!("Last update: {:?} > prev_flush {:?}, sending {} to {:?}", item.last_update, threshold, item.value, master.flip());
-                    return self.get_flush_result(master);
-                }
+            if priority && let Some(&member) = changed.first().filter(|&&m| m < master) {
+                self.current_master = Some(member);
+                return self.get_flush_result(member, eps);
             }
-            None => {
-                let side = if self.left.last_update > self.right.last_update {
-                    Left
-                } else {
-                    Right
-                };
-                let item = self.get_item(side);
-
-                if item.last_update > prev_flush {
-                    self.current_master = Some(side);
-                    return self.get_flush_result(side);
-                }
 
-                if ((self.l2r)(self.left.value) - self.right.value).abs() > FORCE_SYNC_EPS {
-                    let value = self.get_item(side).value;
-                    self.current_master = Some(DEFAULT_MASTER);
-
-                    log::warn!(
-                        "Force synchronising {} to {:?}: ({}, {}) => {}",
-                        self.name,
-                        DEFAULT_MASTER,
-                        self.left.value,
-                        self.right.value,
-                        value
-                    );
-                    return self.get_flush_result(DEFAULT_MASTER);
+            if self.members[master].last_update < Some(threshold) {
+                // log::info!("Last update: {:?} < threshold {:?}, resetting", item.last_update, threshold);
+                self.current_master = None;
+            } else if changed.contains(&master) || (priority && !changed.is_empty()) {
+                // log::info!("Last update: {:?} > prev_flush {:?}, sending {}", item.last_update, threshold, item.value);
+                return self.get_flush_result(master, eps);
+            } else {
+                return Vec::new();
+            }
+        }
+
+        // The member that has been changed most recently becomes the master
+        if let Some(member) = changed
+            .iter()
+            .copied()
+            .max_by_key(|&i| self.members[i].last_update)
+        {
+            self.current_master = Some(member);
+            return self.get_flush_result(member, eps);
+        }
+
+        // Never force values from or to a device that is not answering
+        let online = self.members.iter().all(|m| m.online && m.known);
+
+        if let Some(master) = self.authority().or(self.default_master)
+            && self.settings.strategy != Strategy::NeverForce
+            && online
+        {
+            let updates = self.get_flush_result(master, self.settings.force_eps);
+
+            if !updates.is_empty() {
+                let values: Vec<String> =
+                    self.members.iter().map(|m| m.value.to_string()).collect();
+
+                log::warn!(
+                    "Force synchronising {} to member {}: ({}) => {}",
+                    self.name,
+                    master,
+                    values.join(", "),
+                    self.members[master].value
+                );
+
+                if self.authority().is_none() {
+                    self.current_master = Some(master);
                 }
+                return self.get_flush_result(master, eps);
             }
         }
 
-        None
+        Vec::new()
+    }
+
+    /// Expect the devices to take the values, so the echoes do not count as updates. Values for
+    /// members that are offline are not recorded, the device might never get them.
+    fn expect(&mut self, updates: &[(T, usize)]) {
+        let now = Instant::now();
+
+        for (value, member) in updates {
+            let item = &mut self.members[*member];
+            if !item.online {
+                continue;
+            }
+            item.value = value.clone();
+            // Members that never report back would collect the values forever
+            item.prune(now);
+            item.in_flight.push((value.clone(), now));
+        }
     }
 
-    fn get_transformed(&self, side: Side) -> T {
-        match side {
-            Left => (self.l2r)(self.left.value),
-            Right => (self.r2l)(self.right.value),
+    fn authority(&self) -> Option<usize> {
+        match self.settings.strategy {
+            Strategy::Authoritative(member) => Some(member),
+            _ => None,
         }
     }
 
-    fn get_flush_result(&self, side: Side) -> Option<(T, Side)> {
-        Some((self.get_transformed(side), side.flip()))
+    /// Values of the master for all other members that differ by more than `eps`
+    fn get_flush_result(&self, master: usize, eps: f32) -> Vec<(T, usize)> {
+        let master_item = &self.members[master];
+        let canonical = (master_item.to_canonical)(master_item.value.clone());
+
+        self.members
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != master)
+            .filter_map(|(i, item)| {
+                let value = (item.from_canonical)(canonical.clone());
+                (!item.value.matches(&value, eps)).then_some((value, i))
+            })
+            .collect()
     }
+}
 
-    fn get_item(&self, side: Side) -> &SyncItem {
-        match side {
-            Left => &self.left,
-            Right => &self.right,
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            strategy: Strategy::LastWriter,
+            lockout: MASTER_DURATION,
+            eps: EPS,
+            force_eps: FORCE_SYNC_EPS,
+            echo_eps: EPS,
         }
     }
 }
 
-impl SyncItem {
-    fn new() -> Self {
+impl<T: SyncValue> SyncItem<T> {
+    fn new(to_canonical: Rc<dyn Fn(T) -> T>, from_canonical: Rc<dyn Fn(T) -> T>) -> Self {
         SyncItem {
             value: T::default(),
             last_update: None,
+            online: false,
+            known: false,
+            rejoined: false,
+            in_flight: Vec::new(),
+            to_canonical,
+            from_canonical,
         }
     }
 
-    fn update(&mut self, new_value: T) -> bool {
-        if (self.value - new_value).abs() > EPS {
+    /// Forget the values whose echoes can't arrive anymore
+    fn prune(&mut self, now: Instant) {
+        self.in_flight
+            .retain(|(_, sent)| now.duration_since(*sent) < ECHO_TIMEOUT);
+    }
+
+    fn update(&mut self, new_value: T, settings: &Settings) -> bool {
+        self.known = true;
+
+        self.prune(Instant::now());
+
+        // Devices answer with the values they have been sent, possibly rounded. Older values
+        // can't arrive anymore once an echo has been seen.
+        if let Some(i) = self
+            .in_flight
+            .iter()
+            .position(|(value, _)| value.matches(&new_value, settings.echo_eps))
+        {
+            self.in_flight.drain(..=i);
+            self.value = new_value;
+            return false;
+        }
+
+        if !self.value.matches(&new_value, settings.eps) {
             self.value = new_value;
             self.last_update = Some(Instant::now());
             true
@@ -181,3 +446,268 @@ impl SyncItem {
         }
     }
 }
+
+impl SyncValue for f32 {
+    fn matches(&self, other: &Self, eps: f32) -> bool {
+        (self - other).abs() <= eps
+    }
+}
+
+impl SyncValue for f64 {
+    fn matches(&self, other: &Self, eps: f32) -> bool {
+        (self - other).abs() <= eps as f64
+    }
+}
+
+impl SyncValue for i32 {
+    fn matches(&self, other: &Self, _eps: f32) -> bool {
+        self == other
+    }
+}
+
+impl SyncValue for bool {
+    fn matches(&self, other: &Self, _eps: f32) -> bool {
+        self == other
+    }
+}
+
+impl SyncValue for String {
+    fn matches(&self, other: &Self, _eps: f32) -> bool {
+        self == other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sync(strategy: Strategy, members: usize) -> Sync<f32> {
+        let settings = Settings {
+            strategy,
+            ..Settings::default()
+        };
+        sync_with(settings, members)
+    }
+
+    fn sync_with(settings: Settings, members: usize) -> Sync<f32> {
+        let mut sync = Sync::new("test".to_owned(), settings);
+        for _ in 0..members {
+            let member = sync.add_member(|v| v, |v| v);
+            sync.set_online(member, true);
+        }
+        sync
+    }
+
+    /// Report the initial values of all members and start the synchronisation
+    fn start(sync: &mut Sync<f32>, values: &[f32]) -> Vec<(f32, usize)> {
+        for (member, &value) in values.iter().enumerate() {
+            sync.update(member, value);
+        }
+        sync.reconcile()
+    }
+
+    #[test]
+    fn authority_does_not_block_the_resync() {
+        let mut sync = sync(Strategy::Authoritative(0), 2);
+        assert_eq!(start(&mut sync, &[1.0, 0.0]), vec![(1.0, 1)]);
+        sync.update(1, 1.0);
+        assert!(sync.flush().is_empty());
+
+        sync.update(1, 0.5);
+        assert_eq!(sync.resync(None, false), vec![(1.0, 1)]);
+        assert_eq!(sync.current_master(), Some(0));
+    }
+
+    #[test]
+    fn echo_is_swallowed() {
+        let mut sync = sync_with(
+            Settings {
+                echo_eps: 0.1,
+                ..Settings::default()
+            },
+            2,
+        );
+        assert!(start(&mut sync, &[0.0, 0.0]).is_empty());
+
+        assert!(sync.update(0, 5.0));
+        assert_eq!(sync.flush(), vec![(5.0, 1)]);
+        assert_eq!(sync.current_master(), Some(0));
+
+        // Rounded by the device
+        assert!(!sync.update(1, 5.05));
+        assert_eq!(*sync.value(1), 5.05);
+        assert!(sync.flush().is_empty());
+
+        // Only the first echo counts
+        assert!(sync.update(1, 5.2));
+    }
+
+    #[test]
+    fn echo_expires() {
+        let mut sync = sync_with(
+            Settings {
+                echo_eps: 0.1,
+                ..Settings::default()
+            },
+            2,
+        );
+        start(&mut sync, &[0.0, 0.0]);
+
+        sync.update(0, 5.0);
+        assert_eq!(sync.flush(), vec![(5.0, 1)]);
+
+        for (_, sent) in &mut sync.members[1].in_flight {
+            *sent -= ECHO_TIMEOUT;
+        }
+        assert!(sync.update(1, 5.05));
+        assert!(sync.members[1].in_flight.is_empty());
+    }
+
+    #[test]
+    fn expired_echoes_are_dropped_when_sending() {
+        let mut sync = sync(Strategy::LastWriter, 2);
+        start(&mut sync, &[0.0, 0.0]);
+
+        sync.update(0, 1.0);
+        sync.flush();
+        for (_, sent) in &mut sync.members[1].in_flight {
+            *sent -= ECHO_TIMEOUT;
+        }
+
+        // Member 1 never reports back
+        sync.update(0, 2.0);
+        sync.flush();
+        assert_eq!(sync.members[1].in_flight.len(), 1);
+    }
+
+    #[test]
+    fn priority_takeover() {
+        let mut sync = sync(Strategy::Priority, 3);
+        start(&mut sync, &[0.0, 0.0, 0.0]);
+
+        sync.update(1, 1.0);
+        assert_eq!(sync.flush(), vec![(1.0, 0), (1.0, 2)]);
+        assert_eq!(sync.current_master(), Some(1));
+
+        // Lower priority, reverted while the master is locked
+        sync.update(2, 2.0);
+        assert_eq!(sync.flush(), vec![(1.0, 2)]);
+        assert_eq!(sync.current_master(), Some(1));
+
+        // Higher priority, takes over at once
+        sync.update(0, 3.0);
+        assert_eq!(sync.flush(), vec![(3.0, 1), (3.0, 2)]);
+        assert_eq!(sync.current_master(), Some(0));
+    }
+
+    #[test]
+    fn last_writer_keeps_the_master_during_the_lockout() {
+        let mut sync = sync_with(
+            Settings {
+                lockout: Duration::from_secs(60),
+                ..Settings::default()
+            },
+            2,
+        );
+        start(&mut sync, &[0.0, 0.0]);
+
+        sync.update(1, 1.0);
+        assert_eq!(sync.flush(), vec![(1.0, 0)]);
+
+        sync.update(0, 2.0);
+        assert!(sync.flush().is_empty());
+        assert_eq!(sync.current_master(), Some(1));
+    }
+
+    #[test]
+    fn authoritative_revert() {
+        let mut sync = sync(Strategy::Authoritative(0), 2);
+        assert!(start(&mut sync, &[1.0, 1.0]).is_empty());
+
+        assert!(sync.update(1, 2.0));
+        assert_eq!(sync.flush(), vec![(1.0, 1)]);
+
+        sync.update(0, 3.0);
+        assert_eq!(sync.flush(), vec![(3.0, 1)]);
+    }
+
+    #[test]
+    fn force_sync() {
+        let mut sync = sync(Strategy::LastWriter, 2);
+        start(&mut sync, &[0.0, 5.0]);
+        sync.set_default_master(Some(0));
+
+        assert_eq!(sync.flush(), vec![(0.0, 1)]);
+    }
+
+    #[test]
+    fn force_sync_is_skipped_while_a_member_is_offline() {
+        let mut sync = sync(Strategy::LastWriter, 2);
+        start(&mut sync, &[0.0, 5.0]);
+        sync.set_default_master(Some(0));
+        sync.set_online(1, false);
+
+        assert!(sync.flush().is_empty());
+    }
+
+    #[test]
+    fn never_force() {
+        let mut sync = sync(Strategy::NeverForce, 2);
+        start(&mut sync, &[0.0, 5.0]);
+        sync.set_default_master(Some(0));
+
+        assert!(sync.flush().is_empty());
+        assert!(sync.resync(Some(0), false).is_empty());
+    }
+
+    #[test]
+    fn rejoin() {
+        let mut sync = sync(Strategy::LastWriter, 2);
+        sync.set_default_master(Some(0));
+        start(&mut sync, &[1.0, 1.0]);
+
+        sync.set_online(1, false);
+        sync.update(0, 4.0);
+        assert_eq!(sync.flush(), vec![(4.0, 1)]);
+        assert_eq!(*sync.value(1), 1.0);
+
+        // The value the device kept while it was away is no change
+        sync.set_online(1, true);
+        assert!(!sync.update(1, 1.0));
+        assert_eq!(sync.flush(), vec![(4.0, 1)]);
+        assert!(!sync.update(1, 4.0));
+        assert!(sync.flush().is_empty());
+    }
+
+    #[test]
+    fn rejoining_master_gets_the_values_of_the_others() {
+        let mut sync = sync_with(
+            Settings {
+                lockout: Duration::ZERO,
+                ..Settings::default()
+            },
+            3,
+        );
+        sync.set_default_master(Some(0));
+        start(&mut sync, &[1.0, 1.0, 1.0]);
+
+        sync.set_online(0, false);
+        sync.update(1, 7.0);
+        assert_eq!(sync.flush(), vec![(7.0, 0), (7.0, 2)]);
+
+        sync.set_online(0, true);
+        assert!(!sync.update(0, 1.0));
+        assert_eq!(sync.flush(), vec![(7.0, 0)]);
+    }
+
+    #[test]
+    fn rejoin_without_a_master_changes_nothing() {
+        let mut sync = sync(Strategy::LastWriter, 2);
+        start(&mut sync, &[1.0, 1.0]);
+
+        sync.set_online(1, false);
+        sync.set_online(1, true);
+        assert!(!sync.update(1, 3.0));
+        assert!(sync.flush().is_empty());
+    }
+}
@@ -0,0 +1,238 @@
+use super::scale;
+use crate::value::Value;
+use serde::Deserialize;
+
+/// Conversion of a pair of panner values (e.g. WING pan and width) to a position (e.g. in a
+/// DS100 coordinate mapping) and back, applied after the transform of the member
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Coordinates {
+    /// Range of the first value
+    #[serde(default = "default_pan")]
+    pub pan: [f64; 2],
+    /// Range of the second value
+    #[serde(default = "default_width")]
+    pub width: [f64; 2],
+    /// Range of the X coordinate that `pan` is scaled to
+    #[serde(default = "default_axis")]
+    pub x: [f64; 2],
+    /// Range of the Y coordinate that `width` is scaled to
+    #[serde(default = "default_axis")]
+    pub y: [f64; 2],
+    /// Mirror the X coordinate within its range
+    #[serde(default)]
+    pub invert_x: bool,
+    /// Mirror the Y coordinate within its range
+    #[serde(default)]
+    pub invert_y: bool,
+    /// Use `pan` as the angle and `width` as the distance from a center instead
+    pub polar: Option<Polar>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Polar {
+    /// Position the distance is measured from
+    #[serde(default = "default_center")]
+    pub center: [f64; 2],
+    /// Angle in degrees that `pan` is scaled to, 0 points along the Y axis and positive angles
+    /// towards positive X
+    #[serde(default = "default_angle")]
+    pub angle: [f64; 2],
+    /// Distance from the center that `width` is scaled to
+    #[serde(default = "default_radius")]
+    pub radius: [f64; 2],
+}
+
+impl Coordinates {
+    pub fn validate(&self) -> Result<(), String> {
+        let mut ranges = vec![self.pan, self.width, self.x, self.y];
+        if let Some(polar) = &self.polar {
+            ranges.extend([polar.angle, polar.radius]);
+        }
+
+        if ranges
+            .iter()
+            .any(|r| r[0] == r[1] || !r[0].is_finite() || !r[1].is_finite())
+        {
+            return Err("ranges must not be empty".to_owned());
+        }
+        Ok(())
+    }
+
+    pub fn to_canonical(&self, value: Value) -> Value {
+        value.map_pair(|[pan, width]| {
+            let (x, y) = match &self.polar {
+                None => (
+                    scale(pan, self.pan, self.x),
+                    scale(width, self.width, self.y),
+                ),
+                Some(polar) => {
+                    let angle = scale(pan, self.pan, polar.angle).to_radians();
+                    let radius = scale(width, self.width, polar.radius);
+                    (
+                        polar.center[0] + radius * angle.sin(),
+                        polar.center[1] + radius * angle.cos(),
+                    )
+                }
+            };
+            [self.flip_x(x), self.flip_y(y)]
+        })
+    }
+
+    /// Panner values of a position, positions the panner can't reach are clamped to its ranges
+    pub fn to_member(&self, value: Value) -> Value {
+        value.map_pair(|[x, y]| {
+            let (x, y) = (self.flip_x(x), self.flip_y(y));
+            let [pan, width] = match &self.polar {
+                None => [scale(x, self.x, self.pan), scale(y, self.y, self.width)],
+                Some(polar) => {
+                    let (dx, dy) = (x - polar.center[0], y - polar.center[1]);
+                    // The angle of the center itself is undefined, it keeps the middle of the range
+                    let angle = if dx == 0.0 && dy == 0.0 {
+                        (polar.angle[0] + polar.angle[1]) / 2.0
+                    } else {
+                        dx.atan2(dy).to_degrees()
+                    };
+                    [
+                        scale(angle, polar.angle, self.pan),
+                        scale(dx.hypot(dy), polar.radius, self.width),
+                    ]
+                }
+            };
+            [clamp(pan, self.pan), clamp(width, self.width)]
+        })
+    }
+
+    fn flip_x(&self, x: f64) -> f64 {
+        if self.invert_x {
+            self.x[0] + self.x[1] - x
+        } else {
+            x
+        }
+    }
+
+    fn flip_y(&self, y: f64) -> f64 {
+        if self.invert_y {
+            self.y[0] + self.y[1] - y
+        } else {
+            y
+        }
+    }
+}
+
+/// Limit `x` to a range whose ends may be in any order
+fn clamp(x: f64, range: [f64; 2]) -> f64 {
+    x.clamp(range[0].min(range[1]), range[0].max(range[1]))
+}
+
+fn default_pan() -> [f64; 2] {
+    [-100.0, 100.0]
+}
+
+fn default_width() -> [f64; 2] {
+    [0.0, 100.0]
+}
+
+fn default_axis() -> [f64; 2] {
+    [0.0, 1.0]
+}
+
+fn default_center() -> [f64; 2] {
+    [0.5, 0.0]
+}
+
+fn default_angle() -> [f64; 2] {
+    [-90.0, 90.0]
+}
+
+fn default_radius() -> [f64; 2] {
+    [0.0, 0.5]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Coordinates {
+        toml::from_str(s).unwrap()
+    }
+
+    fn pair(a: f64, b: f64) -> Value {
+        Value::Array(vec![Value::Double(a), Value::Double(b)])
+    }
+
+    fn get(value: Value) -> [f64; 2] {
+        let Value::Array(v) = value else {
+            panic!("not a pair: {}", value);
+        };
+        [v[0].as_f64().unwrap(), v[1].as_f64().unwrap()]
+    }
+
+    fn assert_close(a: [f64; 2], b: [f64; 2]) {
+        assert!(
+            (a[0] - b[0]).abs() < 1e-9 && (a[1] - b[1]).abs() < 1e-9,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    fn assert_round_trip(coordinates: &Coordinates, pan: f64, width: f64) {
+        let position = coordinates.to_canonical(pair(pan, width));
+        assert_close(get(coordinates.to_member(position)), [pan, width]);
+    }
+
+    #[test]
+    fn cartesian() {
+        let c = parse("");
+        assert_close(get(c.to_canonical(pair(-100.0, 0.0))), [0.0, 0.0]);
+        assert_close(get(c.to_canonical(pair(50.0, 25.0))), [0.75, 0.25]);
+        assert_close(get(c.to_member(pair(0.75, 0.25))), [50.0, 25.0]);
+
+        for (pan, width) in [(-100.0, 0.0), (0.0, 50.0), (37.5, 100.0), (100.0, 12.0)] {
+            assert_round_trip(&c, pan, width);
+        }
+    }
+
+    #[test]
+    fn inverted() {
+        let c = parse("x = [-5, 5]\ny = [2, 10]\ninvert_x = true\ninvert_y = true");
+        assert_close(get(c.to_canonical(pair(-100.0, 0.0))), [5.0, 10.0]);
+        assert_close(get(c.to_canonical(pair(100.0, 100.0))), [-5.0, 2.0]);
+        assert_close(get(c.to_member(pair(0.0, 6.0))), [0.0, 50.0]);
+
+        for (pan, width) in [(-100.0, 0.0), (-20.0, 80.0), (100.0, 100.0)] {
+            assert_round_trip(&c, pan, width);
+        }
+    }
+
+    #[test]
+    fn polar() {
+        let c = parse("polar = {}");
+        // Straight ahead, to the right and to the left of the center
+        assert_close(get(c.to_canonical(pair(0.0, 100.0))), [0.5, 0.5]);
+        assert_close(get(c.to_canonical(pair(100.0, 100.0))), [1.0, 0.0]);
+        assert_close(get(c.to_canonical(pair(-100.0, 50.0))), [0.25, 0.0]);
+        // The angle of the center is undefined
+        assert_close(get(c.to_member(pair(0.5, 0.0))), [0.0, 0.0]);
+
+        for (pan, width) in [(-100.0, 10.0), (-50.0, 100.0), (0.0, 30.0), (75.0, 60.0)] {
+            assert_round_trip(&c, pan, width);
+        }
+    }
+
+    #[test]
+    fn unreachable_positions_are_clamped() {
+        let c = parse("polar = {}");
+        // Outside of the radius and behind the center
+        assert_close(get(c.to_member(pair(0.5, 1.0))), [0.0, 100.0]);
+        assert_close(get(c.to_member(pair(0.5, -0.25))), [100.0, 50.0]);
+
+        let c = parse("");
+        assert_close(get(c.to_member(pair(1.5, -0.5))), [100.0, 0.0]);
+
+        let c = parse("pan = [100, -100]");
+        assert_close(get(c.to_member(pair(-1.0, 0.5))), [100.0, 50.0]);
+    }
+}
@@ -0,0 +1,283 @@
+use std::{iter::Peekable, str::Chars};
+use thiserror::Error;
+
+/// Arithmetic expression of the variable `x`, e.g. `20 * log10(max(x, 0.001))`
+#[derive(Clone, Debug)]
+pub enum Expr {
+    Number(f64),
+    X,
+    Neg(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+    Call(Function, Vec<Expr>),
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum Function {
+    Abs,
+    Sqrt,
+    Exp,
+    Ln,
+    Log10,
+    Min,
+    Max,
+    Clamp,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Op(char),
+    Open,
+    Close,
+    Comma,
+}
+
+impl Expr {
+    pub fn parse(s: &str) -> Result<Self, ExprError> {
+        let tokens = tokenize(s).map_err(|reason| ExprError::new(s, reason))?;
+        let mut parser = Parser { tokens, pos: 0 };
+
+        let expr = parser.expr().map_err(|reason| ExprError::new(s, reason))?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(token) => Err(ExprError::new(s, format!("unexpected {:?}", token))),
+        }
+    }
+
+    pub fn eval(&self, x: f64) -> f64 {
+        match self {
+            Expr::Number(v) => *v,
+            Expr::X => x,
+            Expr::Neg(e) => -e.eval(x),
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (a.eval(x), b.eval(x));
+                match op {
+                    Op::Add => a + b,
+                    Op::Sub => a - b,
+                    Op::Mul => a * b,
+                    Op::Div => a / b,
+                    Op::Pow => a.powf(b),
+                }
+            }
+            Expr::Call(function, args) => {
+                let args: Vec<f64> = args.iter().map(|e| e.eval(x)).collect();
+                match function {
+                    Function::Abs => args[0].abs(),
+                    Function::Sqrt => args[0].sqrt(),
+                    Function::Exp => args[0].exp(),
+                    Function::Ln => args[0].ln(),
+                    Function::Log10 => args[0].log10(),
+                    Function::Min => args[0].min(args[1]),
+                    Function::Max => args[0].max(args[1]),
+                    Function::Clamp => args[0].max(args[1]).min(args[2]),
+                }
+            }
+        }
+    }
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<(Self, usize)> {
+        Some(match name {
+            "abs" => (Function::Abs, 1),
+            "sqrt" => (Function::Sqrt, 1),
+            "exp" => (Function::Exp, 1),
+            "ln" => (Function::Ln, 1),
+            "log10" => (Function::Log10, 1),
+            "min" => (Function::Min, 2),
+            "max" => (Function::Max, 2),
+            "clamp" => (Function::Clamp, 3),
+            _ => return None,
+        })
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            _ if c.is_whitespace() => {
+                chars.next();
+            }
+            '0'..='9' | '.' => tokens.push(Token::Number(number(&mut chars)?)),
+            'a'..='z' | 'A'..='Z' | '_' => {
+                let mut ident = String::new();
+                while let Some(&c) = chars.peek().filter(|c| c.is_alphanumeric() || **c == '_') {
+                    ident.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Ident(ident));
+            }
+            '+' | '-' | '*' | '/' | '^' => {
+                tokens.push(Token::Op(c));
+                chars.next();
+            }
+            '(' | ')' | ',' => {
+                tokens.push(match c {
+                    '(' => Token::Open,
+                    ')' => Token::Close,
+                    _ => Token::Comma,
+                });
+                chars.next();
+            }
+            _ => return Err(format!("unexpected character '{}'", c)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn number(chars: &mut Peekable<Chars>) -> Result<f64, String> {
+    let mut s = String::new();
+
+    while let Some(&c) = chars.peek() {
+        let exponent_sign = (c == '+' || c == '-') && s.ends_with(['e', 'E']);
+        if !(c.is_ascii_digit() || c == '.' || c == 'e' || c == 'E' || exponent_sign) {
+            break;
+        }
+        s.push(c);
+        chars.next();
+    }
+
+    s.parse().map_err(|_| format!("invalid number '{}'", s))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(format!("expected {:?}, found {:?}", expected, token)),
+            None => Err(format!("expected {:?}", expected)),
+        }
+    }
+
+    // expr := term (('+' | '-') term)*
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut expr = self.term()?;
+
+        while let Some(Token::Op(c @ ('+' | '-'))) = self.peek() {
+            let op = if *c == '+' { Op::Add } else { Op::Sub };
+            self.next();
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.term()?));
+        }
+
+        Ok(expr)
+    }
+
+    // term := unary (('*' | '/') unary)*
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+
+        while let Some(Token::Op(c @ ('*' | '/'))) = self.peek() {
+            let op = if *c == '*' { Op::Mul } else { Op::Div };
+            self.next();
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.unary()?));
+        }
+
+        Ok(expr)
+    }
+
+    // unary := '-' unary | power
+    fn unary(&mut self) -> Result<Expr, String> {
+        if let Some(Token::Op('-')) = self.peek() {
+            self.next();
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        self.power()
+    }
+
+    // power := primary ('^' unary)?
+    fn power(&mut self) -> Result<Expr, String> {
+        let base = self.primary()?;
+
+        if let Some(Token::Op('^')) = self.peek() {
+            self.next();
+            return Ok(Expr::Binary(
+                Op::Pow,
+                Box::new(base),
+                Box::new(self.unary()?),
+            ));
+        }
+
+        Ok(base)
+    }
+
+    // primary := number | 'x' | constant | function '(' expr (',' expr)* ')' | '(' expr ')'
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(v)) => Ok(Expr::Number(v)),
+            Some(Token::Open) => {
+                let expr = self.expr()?;
+                self.expect(Token::Close)?;
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) => match name.as_str() {
+                "x" => Ok(Expr::X),
+                "pi" => Ok(Expr::Number(std::f64::consts::PI)),
+                "e" => Ok(Expr::Number(std::f64::consts::E)),
+                _ => {
+                    let (function, arity) = Function::from_name(&name)
+                        .ok_or_else(|| format!("unknown function '{}'", name))?;
+
+                    self.expect(Token::Open)?;
+                    let mut args = vec![self.expr()?];
+                    while let Some(Token::Comma) = self.peek() {
+                        self.next();
+                        args.push(self.expr()?);
+                    }
+                    self.expect(Token::Close)?;
+
+                    if args.len() != arity {
+                        return Err(format!("{} takes {} arguments", name, arity));
+                    }
+                    Ok(Expr::Call(function, args))
+                }
+            },
+            Some(token) => Err(format!("unexpected {:?}", token)),
+            None => Err("unexpected end".to_owned()),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("Invalid expression '{expr}': {reason}")]
+pub struct ExprError {
+    expr: String,
+    reason: String,
+}
+
+impl ExprError {
+    fn new(expr: &str, reason: String) -> Self {
+        ExprError {
+            expr: expr.to_owned(),
+            reason,
+        }
+    }
+}
@@ -0,0 +1,447 @@
+mod coordinates;
+mod expr;
+
+pub use coordinates::Coordinates;
+pub use expr::{Expr, ExprError};
+
+use serde::Deserialize;
+use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
+use serde::de::{self, Deserializer, MapAccess, SeqAccess, Visitor};
+use std::fmt;
+
+// Lowest level in dB, used for linear values of 0
+const MIN_DB: f64 = -144.0;
+
+// Range shared by WING levels (-144..10 dB) and DS100 gains (-120..24 dB)
+const DS100_MIN_DB: f64 = -120.0;
+const WING_MAX_DB: f64 = 10.0;
+
+// Values used to check that a transform round-trips if none of its steps defines a domain
+const DEFAULT_DOMAIN: (f64, f64) = (-100.0, 100.0);
+
+// Number of values checked for the round-trip
+const ROUND_TRIP_SAMPLES: usize = 101;
+
+/// Conversion from the value of a member to the canonical value of a mapping and back, a chain
+/// of steps that are applied in order (and in reverse order for the way back). In the
+/// configuration it is either the name of a preset, a single step or a list of steps.
+#[derive(Clone, Debug, Default)]
+pub struct Transform {
+    steps: Vec<Step>,
+}
+
+/// A single conversion, the inverse is derived from the same parameters
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Step {
+    /// Map the range `from` linearly to the range `to`, reversed ranges invert the direction
+    Linear { from: [f64; 2], to: [f64; 2] },
+    /// Limit values to `min..=max`
+    Clamp { min: f64, max: f64 },
+    /// Level in dB to a linear factor
+    DbToLinear,
+    /// Linear factor to a level in dB
+    LinearToDb,
+    /// Piecewise linear interpolation between `[value, canonical]` points, both columns have to
+    /// be strictly increasing or decreasing
+    Table { points: Vec<[f64; 2]> },
+    /// Negate the value
+    Invert,
+    /// User expressions of `x` for both directions, `domain` is the range of values that is used
+    /// to check that they are inverse to each other
+    Expr {
+        to: ExprConfig,
+        from: ExprConfig,
+        domain: Option<[f64; 2]>,
+    },
+}
+
+/// Expression that is parsed when the configuration is read
+#[derive(Deserialize, Clone, Debug)]
+#[serde(try_from = "String")]
+pub struct ExprConfig(Expr);
+
+impl Transform {
+    pub fn new(steps: Vec<Step>) -> Result<Self, String> {
+        for step in &steps {
+            step.validate()?;
+        }
+        Ok(Transform { steps })
+    }
+
+    /// Transform of a built-in name
+    pub fn preset(name: &str) -> Option<Self> {
+        let steps = match name {
+            "identity" => Vec::new(),
+            // WING level (-144..10 dB) stretched to DS100 gain (-120..24 dB)
+            "gain" => vec![Step::Table {
+                points: vec![[-144.0, -120.0], [0.0, 0.0], [10.0, 24.0]],
+            }],
+            // WING level to DS100 gain keeping the absolute level, clamped to the range both
+            // devices support
+            "gain_db" => vec![Step::Clamp {
+                min: DS100_MIN_DB,
+                max: WING_MAX_DB,
+            }],
+            "invert" => vec![Step::Invert],
+            "db_to_linear" => vec![Step::DbToLinear],
+            "linear_to_db" => vec![Step::LinearToDb],
+            _ => return None,
+        };
+        Some(Transform { steps })
+    }
+
+    pub fn to_canonical(&self, x: f64) -> f64 {
+        self.steps.iter().fold(x, |x, step| step.forward(x))
+    }
+
+    pub fn to_member(&self, x: f64) -> f64 {
+        self.steps.iter().rev().fold(x, |x, step| step.inverse(x))
+    }
+
+    /// Largest difference between a value and the result of converting it to the canonical
+    /// value and back, within the domain of the first step that defines one
+    pub fn round_trip_error(&self) -> f64 {
+        let (min, max) = self.domain();
+
+        (0..ROUND_TRIP_SAMPLES)
+            .map(|i| {
+                let x = min + (max - min) * i as f64 / (ROUND_TRIP_SAMPLES - 1) as f64;
+                let error = (self.to_member(self.to_canonical(x)) - x).abs();
+                if error.is_nan() { f64::INFINITY } else { error }
+            })
+            .fold(0.0, f64::max)
+    }
+
+    /// Allowed round-trip error, relative to the size of the domain
+    pub fn round_trip_tolerance(&self) -> f64 {
+        let (min, max) = self.domain();
+        (max - min).abs().max(1.0) * 1e-4
+    }
+
+    fn domain(&self) -> (f64, f64) {
+        self.steps
+            .iter()
+            .find_map(Step::domain)
+            .unwrap_or(DEFAULT_DOMAIN)
+    }
+}
+
+impl Step {
+    fn forward(&self, x: f64) -> f64 {
+        match self {
+            Step::Linear { from, to } => scale(x, *from, *to),
+            Step::Clamp { min, max } => x.clamp(*min, *max),
+            Step::DbToLinear => db_to_linear(x),
+            Step::LinearToDb => linear_to_db(x),
+            Step::Table { points } => interpolate(points, 0, x),
+            Step::Invert => -x,
+            Step::Expr { to, .. } => to.0.eval(x),
+        }
+    }
+
+    fn inverse(&self, x: f64) -> f64 {
+        match self {
+            Step::Linear { from, to } => scale(x, *to, *from),
+            Step::Clamp { min, max } => x.clamp(*min, *max),
+            Step::DbToLinear => linear_to_db(x),
+            Step::LinearToDb => db_to_linear(x),
+            Step::Table { points } => interpolate(points, 1, x),
+            Step::Invert => -x,
+            Step::Expr { from, .. } => from.0.eval(x),
+        }
+    }
+
+    /// Range of values the step is meant for
+    fn domain(&self) -> Option<(f64, f64)> {
+        match self {
+            Step::Linear { from, .. } => Some((from[0], from[1])),
+            Step::Clamp { min, max } => Some((*min, *max)),
+            Step::DbToLinear => Some((MIN_DB, 24.0)),
+            Step::LinearToDb => Some((0.0, db_to_linear(24.0))),
+            Step::Table { points } => Some((points[0][0], points[points.len() - 1][0])),
+            Step::Invert => None,
+            Step::Expr { domain, .. } => domain.map(|[min, max]| (min, max)),
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        match self {
+            Step::Linear { from, to } if from[0] == from[1] || to[0] == to[1] => {
+                Err("linear ranges must not be empty".to_owned())
+            }
+            Step::Clamp { min, max } if min > max => Err("clamp needs min <= max".to_owned()),
+            Step::Table { points } => {
+                if points.len() < 2 {
+                    return Err("tables need at least two points".to_owned());
+                }
+                for column in 0..2 {
+                    let increasing = points[1][column] > points[0][column];
+                    if !points.windows(2).all(|w| {
+                        w[1][column] != w[0][column] && (w[1][column] > w[0][column]) == increasing
+                    }) {
+                        return Err("table columns must be strictly monotonic".to_owned());
+                    }
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Transform {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TransformVisitor;
+
+        impl<'de> Visitor<'de> for TransformVisitor {
+            type Value = Transform;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a transform name, a step or a list of steps")
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<Transform, E> {
+                Transform::preset(name)
+                    .ok_or_else(|| E::custom(format!("unknown transform '{}'", name)))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Transform, A::Error> {
+                let step = Step::deserialize(MapAccessDeserializer::new(map))?;
+                Transform::new(vec![step]).map_err(de::Error::custom)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Transform, A::Error> {
+                let steps = Vec::<Step>::deserialize(SeqAccessDeserializer::new(seq))?;
+                Transform::new(steps).map_err(de::Error::custom)
+            }
+        }
+
+        deserializer.deserialize_any(TransformVisitor)
+    }
+}
+
+impl TryFrom<String> for ExprConfig {
+    type Error = ExprError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Ok(ExprConfig(Expr::parse(&s)?))
+    }
+}
+
+fn scale(x: f64, from: [f64; 2], to: [f64; 2]) -> f64 {
+    to[0] + (x - from[0]) * (to[1] - to[0]) / (from[1] - from[0])
+}
+
+fn db_to_linear(x: f64) -> f64 {
+    if x <= MIN_DB {
+        0.0
+    } else {
+        10f64.powf(x / 20.0)
+    }
+}
+
+fn linear_to_db(x: f64) -> f64 {
+    if x <= 0.0 {
+        MIN_DB
+    } else {
+        (20.0 * x.log10()).max(MIN_DB)
+    }
+}
+
+/// Interpolate between the points, using column `input` as x and the other one as y. Values
+/// outside of the table are clamped to its ends.
+fn interpolate(points: &[[f64; 2]], input: usize, x: f64) -> f64 {
+    let output = 1 - input;
+
+    let (first, last) = (points[0], points[points.len() - 1]);
+    let (low, high) = if first[input] < last[input] {
+        (first, last)
+    } else {
+        (last, first)
+    };
+
+    if x <= low[input] {
+        return low[output];
+    }
+    if x >= high[input] {
+        return high[output];
+    }
+
+    points
+        .windows(2)
+        .find(|w| (w[0][input] <= x) == (x <= w[1][input]))
+        .map(|w| scale(x, [w[0][input], w[1][input]], [w[0][output], w[1][output]]))
+        .unwrap_or(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preset(name: &str) -> Transform {
+        Transform::preset(name).unwrap()
+    }
+
+    #[test]
+    fn gain_db_keeps_absolute_level() {
+        let gain = preset("gain_db");
+
+        for db in [-120.0, -60.0, -10.0, -0.5, 0.0, 6.0, 10.0] {
+            assert_eq!(gain.to_canonical(db), db);
+            assert_eq!(gain.to_member(db), db);
+        }
+    }
+
+    #[test]
+    fn gain_db_clamps_at_the_ends() {
+        let gain = preset("gain_db");
+
+        // WING levels below the DS100 minimum
+        assert_eq!(gain.to_canonical(-144.0), -120.0);
+        assert_eq!(gain.to_canonical(-130.0), -120.0);
+
+        // DS100 gains above the WING maximum
+        assert_eq!(gain.to_member(24.0), 10.0);
+        assert_eq!(gain.to_member(12.0), 10.0);
+    }
+
+    #[test]
+    fn gain_db_round_trips_in_the_shared_range() {
+        assert_eq!(preset("gain_db").round_trip_error(), 0.0);
+    }
+
+    #[test]
+    fn gain_db_round_trip_error_outside_the_shared_range() {
+        let gain = preset("gain_db");
+
+        // The WING goes down to -144 dB, which comes back as the DS100 minimum
+        assert_eq!(gain.to_member(gain.to_canonical(-144.0)), -120.0);
+        // The DS100 goes up to 24 dB, which comes back as the WING maximum
+        assert_eq!(gain.to_canonical(gain.to_member(24.0)), 10.0);
+    }
+
+    #[test]
+    fn gain_stretches_the_range() {
+        let gain = preset("gain");
+
+        assert_eq!(gain.to_canonical(0.0), 0.0);
+        assert!((gain.to_canonical(-10.0) - -8.333).abs() < 1e-3);
+        assert_eq!(gain.to_canonical(10.0), 24.0);
+        assert_eq!(gain.to_member(-120.0), -144.0);
+        assert!(gain.round_trip_error() < 1e-9);
+    }
+
+    fn eval(expr: &str, x: f64) -> f64 {
+        Expr::parse(expr).unwrap().eval(x)
+    }
+
+    fn table(points: &[[f64; 2]]) -> Transform {
+        Transform::new(vec![Step::Table {
+            points: points.to_vec(),
+        }])
+        .unwrap()
+    }
+
+    #[test]
+    fn expr_precedence() {
+        assert_eq!(eval("1 + 2 * 3", 0.0), 7.0);
+        assert_eq!(eval("(1 + 2) * 3", 0.0), 9.0);
+        assert_eq!(eval("x - 1 - 1", 5.0), 3.0);
+        assert_eq!(eval("8 / 4 / 2", 0.0), 1.0);
+    }
+
+    #[test]
+    fn expr_power_is_right_associative_and_binds_tighter_than_negation() {
+        assert_eq!(eval("2^3^2", 0.0), 512.0);
+        assert_eq!(eval("-x^2", 3.0), -9.0);
+        assert_eq!(eval("2^-1", 0.0), 0.5);
+        assert_eq!(eval("2 * x^2", 3.0), 18.0);
+    }
+
+    #[test]
+    fn expr_numbers_and_functions() {
+        assert_eq!(eval("1e-3", 0.0), 0.001);
+        assert_eq!(eval("2.5E+2 - x", 50.0), 200.0);
+        assert_eq!(eval("x-1e3", 0.0), -1000.0);
+        assert_eq!(eval("max(x, 0.001)", 0.0), 0.001);
+        assert_eq!(eval("clamp(x, -1, 1)", 5.0), 1.0);
+        assert_eq!(eval("20 * log10(x)", 10.0), 20.0);
+        assert_eq!(eval("pi", 0.0), std::f64::consts::PI);
+    }
+
+    #[test]
+    fn expr_errors() {
+        for expr in [
+            "x+",
+            "",
+            "(x",
+            "x)",
+            "2 x",
+            "min(x)",
+            "abs(x, 1)",
+            "clamp(x, 0)",
+            "y + 1",
+            "foo(x)",
+            "x % 2",
+            "1.2.3",
+        ] {
+            assert!(Expr::parse(expr).is_err(), "'{}' should not parse", expr);
+        }
+    }
+
+    #[test]
+    fn table_interpolates_increasing_columns() {
+        let t = table(&[[-144.0, -120.0], [0.0, 0.0], [10.0, 24.0]]);
+
+        assert_eq!(t.to_canonical(-72.0), -60.0);
+        assert_eq!(t.to_canonical(5.0), 12.0);
+        assert_eq!(t.to_member(12.0), 5.0);
+        assert_eq!(t.to_member(-60.0), -72.0);
+    }
+
+    #[test]
+    fn table_interpolates_decreasing_columns() {
+        // Decreasing canonical column
+        let t = table(&[[0.0, 1.0], [50.0, 0.5], [100.0, 0.0]]);
+        assert_eq!(t.to_canonical(25.0), 0.75);
+        assert_eq!(t.to_canonical(75.0), 0.25);
+        assert_eq!(t.to_member(0.75), 25.0);
+        assert_eq!(t.to_member(0.25), 75.0);
+
+        // Decreasing value column
+        let t = table(&[[100.0, 0.0], [50.0, 0.5], [0.0, 1.0]]);
+        assert_eq!(t.to_canonical(25.0), 0.75);
+        assert_eq!(t.to_member(0.25), 75.0);
+    }
+
+    #[test]
+    fn table_clamps_at_the_ends() {
+        let t = table(&[[-144.0, -120.0], [0.0, 0.0], [10.0, 24.0]]);
+        assert_eq!(t.to_canonical(-200.0), -120.0);
+        assert_eq!(t.to_canonical(20.0), 24.0);
+        assert_eq!(t.to_member(-130.0), -144.0);
+        assert_eq!(t.to_member(30.0), 10.0);
+
+        let t = table(&[[0.0, 1.0], [50.0, 0.5], [100.0, 0.0]]);
+        assert_eq!(t.to_canonical(-10.0), 1.0);
+        assert_eq!(t.to_canonical(150.0), 0.0);
+        assert_eq!(t.to_member(2.0), 0.0);
+        assert_eq!(t.to_member(-1.0), 100.0);
+    }
+
+    #[test]
+    fn table_validation() {
+        let new = |points: &[[f64; 2]]| {
+            Transform::new(vec![Step::Table {
+                points: points.to_vec(),
+            }])
+        };
+
+        assert!(new(&[[0.0, 0.0]]).is_err());
+        assert!(new(&[[0.0, 0.0], [1.0, 1.0], [1.0, 2.0]]).is_err());
+        assert!(new(&[[0.0, 0.0], [1.0, 1.0], [2.0, 0.5]]).is_err());
+        assert!(new(&[[0.0, 0.0], [2.0, 1.0], [1.0, 2.0]]).is_err());
+        assert!(new(&[[0.0, 2.0], [1.0, 1.0], [2.0, 0.0]]).is_ok());
+    }
+}
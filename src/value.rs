@@ -0,0 +1,179 @@
+use crate::sync::SyncValue;
+use rosc::OscType;
+use serde::Deserialize;
+use std::fmt;
+
+/// Value of a device parameter as received via OSC
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Float(f32),
+    Double(f64),
+    Int(i32),
+    Bool(bool),
+    String(String),
+    /// Several parameters that are synchronised together, e.g. the X and Y position of an object
+    Array(Vec<Value>),
+}
+
+/// Type a parameter is sent as, values of other types are converted
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueKind {
+    Float,
+    Double,
+    Int,
+    Bool,
+    String,
+}
+
+impl Value {
+    pub fn from_osc(arg: &OscType) -> Option<Self> {
+        Some(match arg {
+            OscType::Float(v) => Value::Float(*v),
+            OscType::Double(v) => Value::Double(*v),
+            OscType::Int(v) => Value::Int(*v),
+            OscType::Long(v) => Value::Int(i32::try_from(*v).ok()?),
+            OscType::Bool(v) => Value::Bool(*v),
+            OscType::String(v) => Value::String(v.clone()),
+            _ => return None,
+        })
+    }
+
+    /// Numeric representation of the value, booleans count as 0 and 1
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Float(v) => Some(*v as f64),
+            Value::Double(v) => Some(*v),
+            Value::Int(v) => Some(*v as f64),
+            Value::Bool(v) => Some(*v as i32 as f64),
+            Value::String(_) | Value::Array(_) => None,
+        }
+    }
+
+    /// Numeric difference between two values, for arrays the largest one of their elements
+    pub fn distance(&self, other: &Value) -> Option<f64> {
+        match (self, other) {
+            (Value::Array(a), Value::Array(b)) => a
+                .iter()
+                .zip(b)
+                .filter_map(|(a, b)| a.distance(b))
+                .reduce(f64::max),
+            _ => Some((self.as_f64()? - other.as_f64()?).abs()),
+        }
+    }
+
+    /// Convert the value to the given type, `None` if it has no sensible representation
+    pub fn convert(&self, kind: ValueKind) -> Option<Value> {
+        if let Value::String(s) = self {
+            let s = s.trim();
+            return match kind {
+                ValueKind::Float => s.parse().ok().map(Value::Float),
+                ValueKind::Double => s.parse().ok().map(Value::Double),
+                ValueKind::Int => s.parse().ok().map(Value::Int),
+                ValueKind::Bool => s.parse().ok().map(Value::Bool),
+                ValueKind::String => Some(self.clone()),
+            };
+        }
+
+        let v = self.as_f64()?;
+        Some(match kind {
+            ValueKind::Float => Value::Float(v as f32),
+            ValueKind::Double => Value::Double(v),
+            ValueKind::Int => Value::Int(v.round() as i32),
+            ValueKind::Bool => Value::Bool(v != 0.0),
+            ValueKind::String => Value::String(self.to_string()),
+        })
+    }
+
+    /// Apply `f` to numbers (and the elements of arrays), booleans and strings are returned
+    /// unchanged
+    pub fn map_number(self, f: impl Fn(f64) -> f64 + Copy) -> Value {
+        match self {
+            Value::Float(v) => Value::Float(f(v as f64) as f32),
+            Value::Double(v) => Value::Double(f(v)),
+            Value::Int(v) => Value::Int(f(v as f64).round() as i32),
+            Value::Array(v) => Value::Array(v.into_iter().map(|v| v.map_number(f)).collect()),
+            other => other,
+        }
+    }
+
+    /// Apply `f` to a pair of numbers, anything else is returned unchanged
+    pub fn map_pair(self, f: impl Fn([f64; 2]) -> [f64; 2]) -> Value {
+        let Value::Array(v) = &self else {
+            return self;
+        };
+        let [a, b] = v.as_slice() else {
+            return self;
+        };
+        let (Some(x), Some(y)) = (a.as_f64(), b.as_f64()) else {
+            return self;
+        };
+
+        let [x, y] = f([x, y]);
+        Value::Array(vec![
+            a.clone().map_number(|_| x),
+            b.clone().map_number(|_| y),
+        ])
+    }
+}
+
+impl Default for Value {
+    fn default() -> Self {
+        Value::Float(0.0)
+    }
+}
+
+impl From<Value> for OscType {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Float(v) => OscType::Float(v),
+            Value::Double(v) => OscType::Double(v),
+            Value::Int(v) => OscType::Int(v),
+            Value::Bool(v) => OscType::Bool(v),
+            Value::String(v) => OscType::String(v),
+            Value::Array(v) => OscType::Array(v.into_iter().collect()),
+        }
+    }
+}
+
+impl SyncValue for Value {
+    fn matches(&self, other: &Self, eps: f32) -> bool {
+        match (self, other) {
+            (Value::Float(a), Value::Float(b)) => a.matches(b, eps),
+            (Value::Double(a), Value::Double(b)) => a.matches(b, eps),
+            (Value::Int(a), Value::Int(b)) => a.matches(b, eps),
+            (Value::Bool(a), Value::Bool(b)) => a.matches(b, eps),
+            (Value::String(a), Value::String(b)) => a.matches(b, eps),
+            (Value::Array(a), Value::Array(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.matches(b, eps))
+            }
+            // Devices might use different types for the same thing, e.g. an int and a bool mute
+            (a, b) => match (a.as_f64(), b.as_f64()) {
+                (Some(a), Some(b)) => a.matches(&b, eps),
+                _ => false,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Float(v) => write!(f, "{}", v),
+            Value::Double(v) => write!(f, "{}", v),
+            Value::Int(v) => write!(f, "{}", v),
+            Value::Bool(v) => write!(f, "{}", v),
+            Value::String(v) => write!(f, "{:?}", v),
+            Value::Array(v) => {
+                write!(f, "(")?;
+                for (i, v) in v.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}